@@ -0,0 +1,425 @@
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use libm::F32Ext;
+
+use crate::{
+    multi_driver::{copy_axes, Axes},
+    utils::Stopwatch,
+    Device, Driver, MotionProfile,
+};
+use core::{f32::consts::PI, time::Duration};
+
+/// The plane an arc is drawn in, following the G-code `G17`/`G18`/`G19`
+/// conventions.
+///
+/// Axes are referred to by their index in the [`crate::MultiDriver`], so the
+/// first axis is `X`, the second is `Y`, and the third is `Z`. The remaining
+/// axis (perpendicular to the plane) may also move to trace out a helix.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Plane {
+    /// The `X`-`Y` plane (`G17`), with `Z` as the helical axis.
+    #[default]
+    XY,
+    /// The `Z`-`X` plane (`G18`), with `Y` as the helical axis.
+    ZX,
+    /// The `Y`-`Z` plane (`G19`), with `X` as the helical axis.
+    YZ,
+}
+
+impl Plane {
+    /// The indices of the two axes in this plane.
+    pub fn axes(self) -> (usize, usize) {
+        match self {
+            Plane::XY => (0, 1),
+            Plane::ZX => (2, 0),
+            Plane::YZ => (1, 2),
+        }
+    }
+}
+
+/// Which way to go around an arc, when looking down on the [`Plane`] from
+/// the positive end of the perpendicular axis.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ArcDirection {
+    /// Clockwise (`G2`).
+    Clockwise,
+    /// Counter-clockwise (`G3`).
+    CounterClockwise,
+}
+
+/// How the centre of an arc is specified.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ArcCentre {
+    /// The centre's offset from the start position in steps, along the
+    /// plane's first and second axes (the `I`/`J`/`K` words in G-code).
+    ///
+    /// If the start and end positions are the same, this traces a full
+    /// circle.
+    Offset(f32, f32),
+    /// The arc's radius in steps (the `R` word in G-code). A positive radius
+    /// selects the shorter of the two possible arcs, while a negative radius
+    /// selects the longer one.
+    Radius(f32),
+}
+
+/// Reasons an arc can't be drawn.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ArcError {
+    /// The radius is too small for an arc to reach between the start and end
+    /// positions.
+    RadiusTooSmall,
+    /// The centre is at the start position, so there is no arc to draw.
+    ZeroRadius,
+}
+
+/// The equal chords used to approximate an arc within a [`Plane`], short
+/// enough that they never stray further than the tolerance from the true arc.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Chords {
+    centre: (f32, f32),
+    radius: f32,
+    start_angle: f32,
+    chord_angle: f32,
+    count: u32,
+    end: (f32, f32),
+}
+
+impl Chords {
+    /// Work out the chords for an arc from `start` to `end`, where both
+    /// points are given in the plane's coordinates.
+    pub(crate) fn new(
+        start: (f32, f32),
+        end: (f32, f32),
+        centre: ArcCentre,
+        direction: ArcDirection,
+        tolerance: f32,
+    ) -> Result<Chords, ArcError> {
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let offset = match centre {
+            ArcCentre::Offset(i, j) => (i, j),
+            ArcCentre::Radius(r) => centre_from_radius(dx, dy, r, direction)?,
+        };
+        let radius = offset.0.hypot(offset.1);
+
+        if radius == 0.0 {
+            return Err(ArcError::ZeroRadius);
+        }
+
+        let sweep = sweep_angle(offset, (dx, dy), direction);
+
+        // the sagitta of each chord, r * (1 - cos(theta / 2)), needs to be
+        // within the tolerance
+        let max_chord_angle = if tolerance < radius {
+            2.0 * (1.0 - tolerance / radius).acos()
+        } else {
+            PI
+        };
+        let count = (sweep.abs() / max_chord_angle).ceil().max(1.0) as u32;
+
+        Ok(Chords {
+            centre: (start.0 + offset.0, start.1 + offset.1),
+            radius,
+            start_angle: (-offset.1).atan2(-offset.0),
+            chord_angle: sweep / count as f32,
+            count,
+            end,
+        })
+    }
+
+    /// The number of chords.
+    #[cfg(feature = "gcode")]
+    pub(crate) fn count(&self) -> u32 { self.count }
+
+    pub(crate) fn radius(&self) -> f32 { self.radius }
+
+    /// The combined length of every chord.
+    pub(crate) fn length(&self) -> f32 {
+        self.count as f32
+            * 2.0
+            * self.radius
+            * (self.chord_angle.abs() / 2.0).sin()
+    }
+
+    /// Find the point a certain fraction of the way along the chords.
+    pub(crate) fn point(&self, fraction: f32) -> (f32, f32) {
+        let progress = fraction * self.count as f32;
+        let chord = (progress.floor() as u32).min(self.count - 1);
+        let t = progress - chord as f32;
+
+        let from = self.vertex(chord);
+        let to = self.vertex(chord + 1);
+
+        (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)
+    }
+
+    /// The point where one chord ends and the next begins, where vertex `0`
+    /// is the start of the arc and vertex [`Chords::count()`] is its end.
+    pub(crate) fn vertex(&self, index: u32) -> (f32, f32) {
+        if index == self.count {
+            // make sure we always finish exactly on the end point
+            return self.end;
+        }
+
+        let angle = self.start_angle + self.chord_angle * index as f32;
+
+        (
+            self.centre.0 + self.radius * angle.cos(),
+            self.centre.1 + self.radius * angle.sin(),
+        )
+    }
+}
+
+/// An arc (or helix) currently being executed.
+///
+/// The arc is approximated by [`Chords`], and a single motion profile
+/// carries the motors along the entire path so we don't stop at every chord.
+pub(crate) struct ArcMove {
+    /// The indices of the plane's two axes.
+    plane: (usize, usize),
+    chords: Chords,
+    start: Axes<i64>,
+    end: Axes<i64>,
+    /// The total length of the path, in steps.
+    length: f32,
+    profile: MotionProfile,
+    stopwatch: Stopwatch,
+    done: bool,
+}
+
+impl ArcMove {
+    /// Plan an arc from the drivers' current positions to `end`.
+    pub(crate) fn new(
+        drivers: &[Driver],
+        end: &[i64],
+        centre: ArcCentre,
+        direction: ArcDirection,
+        plane: Plane,
+        tolerance: f32,
+        max_path_speed: Option<f32>,
+    ) -> Result<ArcMove, ArcError> {
+        let plane = plane.axes();
+        let start: Axes<i64> =
+            drivers.iter().map(|d| d.current_position()).collect();
+        let end = copy_axes(end);
+
+        let chords = Chords::new(
+            (start[plane.0] as f32, start[plane.1] as f32),
+            (end[plane.0] as f32, end[plane.1] as f32),
+            centre,
+            direction,
+            tolerance,
+        )?;
+        let radius = chords.radius();
+
+        // any other axes move linearly, turning the arc into a helix
+        let planar_length = chords.length();
+        let linear_length_squared: f32 = start
+            .iter()
+            .zip(end.iter())
+            .enumerate()
+            .filter(|&(i, _)| i != plane.0 && i != plane.1)
+            .map(|(_, (s, e))| ((e - s) * (e - s)) as f32)
+            .sum();
+        let length = (planar_length * planar_length + linear_length_squared)
+            .sqrt()
+            .max(1.0);
+
+        // Split each plane axis's acceleration budget evenly between
+        // speeding up along the path and the centripetal acceleration needed
+        // to go around the curve.
+        let mut max_speed = max_path_speed.unwrap_or(f32::MAX);
+        let mut acceleration = f32::MAX;
+
+        for (i, driver) in drivers.iter().enumerate() {
+            let share = if i == plane.0 || i == plane.1 {
+                planar_length / length
+            } else {
+                (end[i] - start[i]).abs() as f32 / length
+            };
+
+            if share == 0.0 {
+                continue;
+            }
+
+            max_speed = max_speed.min(driver.max_speed() / share);
+
+            if i == plane.0 || i == plane.1 {
+                let budget = driver.acceleration() / 2.0_f32.sqrt();
+                acceleration = acceleration.min(budget / share);
+                max_speed = max_speed.min((budget * radius).sqrt() / share);
+            } else {
+                acceleration = acceleration.min(driver.acceleration() / share);
+            }
+        }
+
+        Ok(ArcMove {
+            plane,
+            chords,
+            start,
+            end,
+            length,
+            profile: MotionProfile::new(length, 0.0, max_speed, acceleration),
+            stopwatch: Stopwatch::new(),
+            done: false,
+        })
+    }
+
+    /// Step each axis towards where it should be at this point in time.
+    pub(crate) fn poll<D: Device>(
+        &mut self,
+        drivers: &mut [Driver],
+        devices: &mut [D],
+        now: Duration,
+    ) -> Result<(), D::Error> {
+        let elapsed = self.stopwatch.elapsed(now);
+        let fraction = if elapsed >= self.profile.total_time() {
+            1.0
+        } else {
+            (self.profile.distance_at(elapsed) / self.length).min(1.0)
+        };
+        let on_arc = self.chords.point(fraction);
+
+        for (i, (driver, dev)) in
+            drivers.iter_mut().zip(devices.iter_mut()).enumerate()
+        {
+            let expected = if i == self.plane.0 {
+                on_arc.0.round() as i64
+            } else if i == self.plane.1 {
+                on_arc.1.round() as i64
+            } else {
+                let delta = (self.end[i] - self.start[i]) as f32;
+                self.start[i] + (delta * fraction).round() as i64
+            };
+            let current = driver.current_position();
+
+            if expected != current {
+                driver.take_step(dev, now, expected > current)?;
+            }
+        }
+
+        self.done = fraction >= 1.0;
+        Ok(())
+    }
+
+    pub(crate) fn is_finished(&self, drivers: &[Driver]) -> bool {
+        self.done && drivers.iter().all(|d| d.distance_to_go() == 0)
+    }
+}
+
+/// Find the centre of an arc (relative to the start) given its radius, using
+/// the same convention as G-code's `R` word.
+fn centre_from_radius(
+    dx: f32,
+    dy: f32,
+    radius: f32,
+    direction: ArcDirection,
+) -> Result<(f32, f32), ArcError> {
+    let distance = dx.hypot(dy);
+    let discriminant = 4.0 * radius * radius - distance * distance;
+
+    if distance == 0.0 || discriminant < 0.0 {
+        return Err(ArcError::RadiusTooSmall);
+    }
+
+    // how far the centre is from the chord's midpoint, as a multiple of the
+    // chord length
+    let mut h = -discriminant.sqrt() / distance;
+
+    if direction == ArcDirection::CounterClockwise {
+        h = -h;
+    }
+    if radius < 0.0 {
+        // go the long way around
+        h = -h;
+    }
+
+    Ok((0.5 * (dx - dy * h), 0.5 * (dy + dx * h)))
+}
+
+/// The signed angle swept when going from the start to the end, where
+/// positive angles are counter-clockwise.
+fn sweep_angle(
+    centre: (f32, f32),
+    end: (f32, f32),
+    direction: ArcDirection,
+) -> f32 {
+    // vectors from the centre to the start and end points
+    let from = (-centre.0, -centre.1);
+    let to = (end.0 - centre.0, end.1 - centre.1);
+
+    let cross = from.0 * to.1 - from.1 * to.0;
+    let dot = from.0 * to.0 + from.1 * to.1;
+    let mut sweep = cross.atan2(dot);
+
+    // a tiny epsilon means start == end gives a full circle
+    match direction {
+        ArcDirection::Clockwise if sweep >= -1e-6 => sweep -= 2.0 * PI,
+        ArcDirection::CounterClockwise if sweep <= 1e-6 => sweep += 2.0 * PI,
+        _ => {},
+    }
+
+    sweep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(left: (f32, f32), right: (f32, f32)) {
+        assert!(
+            (left.0 - right.0).abs() < 1e-3 && (left.1 - right.1).abs() < 1e-3,
+            "{:?} != {:?}",
+            left,
+            right
+        );
+    }
+
+    #[test]
+    fn centre_of_a_quarter_circle() {
+        // (0, 0) -> (10, 10) with radius 10
+        let ccw = centre_from_radius(
+            10.0,
+            10.0,
+            10.0,
+            ArcDirection::CounterClockwise,
+        )
+        .unwrap();
+        assert_close(ccw, (0.0, 10.0));
+
+        let cw = centre_from_radius(10.0, 10.0, 10.0, ArcDirection::Clockwise)
+            .unwrap();
+        assert_close(cw, (10.0, 0.0));
+
+        // a negative radius takes the long way around
+        let long =
+            centre_from_radius(10.0, 10.0, -10.0, ArcDirection::Clockwise)
+                .unwrap();
+        assert_close(long, (0.0, 10.0));
+    }
+
+    #[test]
+    fn radius_must_reach_the_end_point() {
+        assert_eq!(
+            centre_from_radius(30.0, 0.0, 10.0, ArcDirection::Clockwise),
+            Err(ArcError::RadiusTooSmall)
+        );
+    }
+
+    #[test]
+    fn sweep_directions() {
+        let quarter = sweep_angle(
+            (0.0, 10.0),
+            (10.0, 10.0),
+            ArcDirection::CounterClockwise,
+        );
+        assert!((quarter - PI / 2.0).abs() < 1e-4);
+
+        let three_quarters =
+            sweep_angle((0.0, 10.0), (10.0, 10.0), ArcDirection::Clockwise);
+        assert!((three_quarters + 3.0 * PI / 2.0).abs() < 1e-4);
+
+        let full_circle =
+            sweep_angle((0.0, 10.0), (0.0, 0.0), ArcDirection::Clockwise);
+        assert!((full_circle + 2.0 * PI).abs() < 1e-4);
+    }
+}
@@ -1,19 +1,375 @@
-use core::time::Duration;
+#[cfg(any(feature = "manual-clock", test))]
+use crate::Driver;
+use core::{cell::Cell, time::Duration};
 
 /// Something which records the elapsed real time.
 ///
 /// This uses shared references because it may be shared between multiple
 /// components at any one time.
+///
+/// The time should never go backwards. Hardware timers which wrap around can
+/// be made monotonic with a [`WrappingClock`], although the drivers will
+/// recover (instead of panicking) if time does go backwards.
 pub trait SystemClock {
     /// The amount of time that has passed since a clock-specific reference
     /// point (e.g. device startup or the unix epoch).
     fn elapsed(&self) -> Duration;
 }
 
-impl<'a, C: SystemClock> SystemClock for &'a C {
+impl<C: SystemClock> SystemClock for &C {
     fn elapsed(&self) -> Duration { (*self).elapsed() }
 }
 
+/// A hardware tick counter which wraps around to zero when it overflows.
+pub trait Ticks: Copy {
+    /// How many ticks have happened since `earlier`, assuming the counter has
+    /// wrapped around at most once.
+    fn ticks_since(self, earlier: Self) -> u64;
+}
+
+impl Ticks for u16 {
+    fn ticks_since(self, earlier: u16) -> u64 {
+        u64::from(self.wrapping_sub(earlier))
+    }
+}
+
+impl Ticks for u32 {
+    fn ticks_since(self, earlier: u32) -> u64 {
+        u64::from(self.wrapping_sub(earlier))
+    }
+}
+
+impl Ticks for u64 {
+    fn ticks_since(self, earlier: u64) -> u64 { self.wrapping_sub(earlier) }
+}
+
+/// A [`SystemClock`] which extends a wrapping hardware tick counter (e.g.
+/// the 32-bit microsecond counter returned by Arduino's `micros()`) into a
+/// monotonic clock.
+///
+/// Every time the clock is read, the ticks since the previous reading are
+/// added to a 64-bit total, so wrapping around is handled transparently as
+/// long as the clock is read at least once per wrap-around period (about 71
+/// minutes for a 32-bit microsecond counter, or 65 milliseconds for a 16-bit
+/// one).
+///
+/// With the `fugit` feature, a timer which hands out [`fugit::Instant`]s can
+/// be used with [`WrappingClock::from_fugit()`]. Similarly, the
+/// `embedded-time` feature adds [`WrappingClock::from_embedded_time()`] for
+/// [`embedded_time::Instant`]s.
+///
+/// When a tick lasts a whole number of nanoseconds (any frequency which
+/// divides 1 GHz, e.g. 1 MHz or 8 MHz) converting to a [`Duration`] is a
+/// single 64-bit multiplication. Other tick periods (e.g. a 32.768 kHz
+/// crystal) fall back to 128-bit arithmetic, which is noticeably slower on
+/// small microcontrollers without hardware division.
+///
+/// ```rust
+/// use accel_stepper::{SystemClock, WrappingClock};
+/// use core::{cell::Cell, time::Duration};
+///
+/// // pretend this is a 16-bit timer running at 1 MHz, about to overflow
+/// let timer = Cell::new(65_000_u16);
+/// let clock = WrappingClock::new(1_000_000, || timer.get());
+///
+/// timer.set(timer.get().wrapping_add(1000));
+/// assert_eq!(timer.get(), 464);
+/// assert_eq!(clock.elapsed(), Duration::from_micros(1000));
+/// ```
+pub struct WrappingClock<T, F> {
+    read_ticks: F,
+    /// The length of a tick in seconds, as a fraction.
+    tick_period: (u32, u32),
+    /// The length of a tick in nanoseconds, if it is a whole number.
+    nanos_per_tick: Option<u64>,
+    last_reading: Cell<T>,
+    total_ticks: Cell<u64>,
+}
+
+impl<T, F> WrappingClock<T, F>
+where
+    T: Ticks,
+    F: Fn() -> T,
+{
+    /// Create a new [`WrappingClock`] for a counter which ticks at
+    /// `frequency` Hz, using the current reading as the reference point.
+    pub fn new(frequency: u32, read_ticks: F) -> WrappingClock<T, F> {
+        WrappingClock::with_tick_period(1, frequency, read_ticks)
+    }
+
+    /// Create a new [`WrappingClock`] for a counter where each tick lasts
+    /// `numerator / denominator` seconds, using the current reading as the
+    /// reference point.
+    pub fn with_tick_period(
+        numerator: u32,
+        denominator: u32,
+        read_ticks: F,
+    ) -> WrappingClock<T, F> {
+        debug_assert!(numerator > 0 && denominator > 0);
+        let last_reading = Cell::new(read_ticks());
+        let period_nanos = u64::from(numerator) * 1_000_000_000;
+        let nanos_per_tick = if period_nanos % u64::from(denominator) == 0 {
+            Some(period_nanos / u64::from(denominator))
+        } else {
+            None
+        };
+
+        WrappingClock {
+            read_ticks,
+            tick_period: (numerator, denominator),
+            nanos_per_tick,
+            last_reading,
+            total_ticks: Cell::new(0),
+        }
+    }
+
+    /// The length of each tick in seconds, as a `(numerator, denominator)`
+    /// fraction.
+    pub fn tick_period(&self) -> (u32, u32) { self.tick_period }
+
+    /// The total number of ticks since the clock was created.
+    pub fn ticks(&self) -> u64 {
+        let reading = (self.read_ticks)();
+        let ticks = reading.ticks_since(self.last_reading.get());
+
+        self.last_reading.set(reading);
+        self.total_ticks.set(self.total_ticks.get() + ticks);
+        self.total_ticks.get()
+    }
+}
+
+impl<T, F> SystemClock for WrappingClock<T, F>
+where
+    T: Ticks,
+    F: Fn() -> T,
+{
+    fn elapsed(&self) -> Duration {
+        let ticks = self.ticks();
+
+        if let Some(nanos) =
+            self.nanos_per_tick.and_then(|n| ticks.checked_mul(n))
+        {
+            return Duration::from_nanos(nanos);
+        }
+
+        let (numerator, denominator) = self.tick_period;
+        let scaled = u128::from(ticks) * u128::from(numerator);
+        let denominator = u128::from(denominator);
+        let secs = scaled / denominator;
+        let nanos = (scaled % denominator) * 1_000_000_000 / denominator;
+
+        Duration::new(secs as u64, nanos as u32)
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl<const NOM: u32, const DENOM: u32> Ticks
+    for fugit::Instant<u32, NOM, DENOM>
+{
+    fn ticks_since(self, earlier: Self) -> u64 {
+        self.ticks().ticks_since(earlier.ticks())
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl<const NOM: u32, const DENOM: u32> Ticks
+    for fugit::Instant<u64, NOM, DENOM>
+{
+    fn ticks_since(self, earlier: Self) -> u64 {
+        self.ticks().ticks_since(earlier.ticks())
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl<T, F, const NOM: u32, const DENOM: u32>
+    WrappingClock<fugit::Instant<T, NOM, DENOM>, F>
+where
+    fugit::Instant<T, NOM, DENOM>: Ticks,
+    F: Fn() -> fugit::Instant<T, NOM, DENOM>,
+{
+    /// Create a new [`WrappingClock`] from a timer which hands out
+    /// [`fugit::Instant`]s, using the [`fugit::Instant`]'s tick rate.
+    ///
+    /// Requires the `fugit` feature.
+    ///
+    /// ```rust
+    /// use accel_stepper::{SystemClock, WrappingClock};
+    /// use core::{cell::Cell, time::Duration};
+    /// use fugit::{ExtU32, Instant};
+    ///
+    /// // a 1 MHz timer which is about to overflow
+    /// let timer = Cell::new(Instant::<u32, 1, 1_000_000>::from_ticks(u32::MAX));
+    /// let clock = WrappingClock::from_fugit(|| timer.get());
+    ///
+    /// timer.set(timer.get() + 250.millis());
+    /// assert_eq!(clock.elapsed(), Duration::from_millis(250));
+    /// ```
+    pub fn from_fugit(read_instant: F) -> Self {
+        WrappingClock::with_tick_period(NOM, DENOM, read_instant)
+    }
+}
+
+#[cfg(feature = "embedded-time")]
+impl<C> Ticks for embedded_time::Instant<C>
+where
+    C: embedded_time::Clock,
+    C::T: Ticks,
+{
+    fn ticks_since(self, earlier: Self) -> u64 {
+        let ticks = self.duration_since_epoch().integer();
+        ticks.ticks_since(earlier.duration_since_epoch().integer())
+    }
+}
+
+#[cfg(feature = "embedded-time")]
+impl<C, F> WrappingClock<embedded_time::Instant<C>, F>
+where
+    C: embedded_time::Clock,
+    C::T: Ticks,
+    F: Fn() -> embedded_time::Instant<C>,
+{
+    /// Create a new [`WrappingClock`] from a timer which hands out
+    /// [`embedded_time::Instant`]s, using the clock's
+    /// [`embedded_time::Clock::SCALING_FACTOR`] as the tick period.
+    ///
+    /// Requires the `embedded-time` feature.
+    ///
+    /// ```rust
+    /// use accel_stepper::{SystemClock, WrappingClock};
+    /// use core::{cell::Cell, time::Duration};
+    /// use embedded_time::{clock, fraction::Fraction, Clock, Instant};
+    ///
+    /// /// A 1 MHz timer which is about to overflow.
+    /// #[derive(Debug)]
+    /// struct Timer(Cell<u32>);
+    ///
+    /// impl Clock for Timer {
+    ///     type T = u32;
+    ///     const SCALING_FACTOR: Fraction = Fraction::new(1, 1_000_000);
+    ///
+    ///     fn try_now(&self) -> Result<Instant<Self>, clock::Error> {
+    ///         Ok(Instant::new(self.0.get()))
+    ///     }
+    /// }
+    ///
+    /// let timer = Timer(Cell::new(u32::MAX));
+    /// let clock = WrappingClock::from_embedded_time(|| timer.try_now().unwrap());
+    ///
+    /// timer.0.set(timer.0.get().wrapping_add(250_000));
+    /// assert_eq!(clock.elapsed(), Duration::from_millis(250));
+    /// ```
+    pub fn from_embedded_time(read_instant: F) -> Self {
+        let period = C::SCALING_FACTOR;
+
+        WrappingClock::with_tick_period(
+            *period.numerator(),
+            *period.denominator(),
+            read_instant,
+        )
+    }
+}
+
+/// A clock which only moves when told to, for writing deterministic tests and
+/// simulations.
+///
+/// Time can be moved forward explicitly with [`ManualClock::advance()`],
+/// automatically by a fixed amount every time the clock is read (see
+/// [`ManualClock::with_auto_advance()`]), or straight to the moment a
+/// [`Driver`]'s next step is due with [`ManualClock::advance_to_next_step()`].
+///
+/// Requires the `manual-clock` feature.
+///
+/// ```rust
+/// use accel_stepper::{Driver, ManualClock, SystemClock};
+/// use core::time::Duration;
+///
+/// let mut axis = Driver::new();
+/// axis.set_max_speed(100.0);
+/// axis.set_acceleration(50.0);
+/// axis.move_to(10);
+///
+/// let clock = ManualClock::new();
+/// let mut steps = 0;
+/// let mut dev = accel_stepper::func_device(|| steps += 1, || {});
+///
+/// while axis.is_running() {
+///     // skip straight to the next step instead of busy polling
+///     clock.advance_to_next_step(&axis);
+///     axis.poll(&mut dev, &clock)?;
+/// }
+///
+/// assert_eq!(axis.current_position(), 10);
+/// assert!(clock.now() > Duration::from_millis(500));
+/// # Result::<(), Box<dyn std::error::Error>>::Ok(())
+/// ```
+#[cfg(any(feature = "manual-clock", test))]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+    auto_advance: Duration,
+}
+
+#[cfg(any(feature = "manual-clock", test))]
+impl ManualClock {
+    /// Create a new [`ManualClock`] starting at zero.
+    pub fn new() -> ManualClock { ManualClock::default() }
+
+    /// Create a new [`ManualClock`] which moves forward by `step` after each
+    /// call to [`SystemClock::elapsed()`].
+    pub fn with_auto_advance(step: Duration) -> ManualClock {
+        ManualClock {
+            now: Cell::new(Duration::new(0, 0)),
+            auto_advance: step,
+        }
+    }
+
+    /// The current time, without advancing the clock.
+    pub fn now(&self) -> Duration { self.now.get() }
+
+    /// Jump to a particular time.
+    pub fn set(&self, now: Duration) { self.now.set(now); }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+
+    /// Set how far the clock moves forward each time it is read.
+    pub fn set_auto_advance(&mut self, step: Duration) {
+        self.auto_advance = step;
+    }
+
+    /// Get the auto-advance step.
+    pub fn auto_advance(&self) -> Duration { self.auto_advance }
+
+    /// Move the clock forward to when the [`Driver`]'s next step is due (see
+    /// [`Driver::next_step_time()`]).
+    ///
+    /// Returns `false` without touching the clock if the [`Driver`] isn't
+    /// moving. The clock never goes backwards, so if the step is already
+    /// overdue the time is left as-is.
+    pub fn advance_to_next_step(&self, driver: &Driver) -> bool {
+        match driver.next_step_time() {
+            Some(deadline) => {
+                if deadline > self.now() {
+                    self.set(deadline);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(any(feature = "manual-clock", test))]
+impl SystemClock for ManualClock {
+    fn elapsed(&self) -> Duration {
+        let now = self.now();
+        self.advance(self.auto_advance);
+        now
+    }
+}
+
 /// A monotonically non-decreasing clock backed by the operating system.
 ///
 /// Requires the `std` feature.
@@ -41,3 +397,126 @@ impl Default for OperatingSystemClock {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_around_many_times() {
+        let timer = Cell::new(0_u16);
+        let clock = WrappingClock::new(1000, || timer.get());
+
+        // 10 seconds worth of 1ms ticks, read every 7 ticks
+        for i in 1..=10_000_u32 {
+            timer.set(timer.get().wrapping_add(1));
+
+            if i % 7 == 0 {
+                assert_eq!(clock.ticks(), u64::from(i));
+            }
+        }
+
+        assert_eq!(clock.elapsed(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn manual_clock_auto_advance() {
+        let clock = ManualClock::with_auto_advance(Duration::from_millis(5));
+
+        assert_eq!(clock.elapsed(), Duration::from_millis(0));
+        assert_eq!(clock.elapsed(), Duration::from_millis(5));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.now(), Duration::from_millis(1010));
+        assert_eq!(clock.elapsed(), Duration::from_millis(1010));
+    }
+
+    #[test]
+    fn manual_clock_jumps_to_the_next_step() {
+        let clock = ManualClock::new();
+        let mut driver = Driver::new();
+        driver.set_max_speed(10.0);
+        driver.set_speed(10.0);
+
+        assert!(clock.advance_to_next_step(&driver));
+        assert_eq!(clock.now(), Duration::from_millis(100));
+
+        // not moving, so there's nowhere to jump to
+        driver.set_speed(0.0);
+        assert!(!clock.advance_to_next_step(&driver));
+        assert_eq!(clock.now(), Duration::from_millis(100));
+    }
+
+    #[test]
+    #[cfg(feature = "fugit")]
+    fn fugit_instants_at_32_khz() {
+        type Instant = fugit::Instant<u32, 1, 32_768>;
+
+        let timer = Cell::new(Instant::from_ticks(u32::MAX - 100));
+        let clock = WrappingClock::from_fugit(|| timer.get());
+
+        timer.set(Instant::from_ticks(32_768 * 3 + 16_384 - 101));
+        assert_eq!(clock.elapsed(), Duration::from_millis(3500));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-time")]
+    fn embedded_time_instants_at_32_khz() {
+        use embedded_time::{clock, fraction::Fraction, Instant};
+
+        #[derive(Debug)]
+        struct Timer;
+
+        impl embedded_time::Clock for Timer {
+            type T = u32;
+
+            const SCALING_FACTOR: Fraction = Fraction::new(1, 32_768);
+
+            fn try_now(&self) -> Result<Instant<Self>, clock::Error> {
+                unimplemented!()
+            }
+        }
+
+        let start = u32::MAX - 100;
+        let timer = Cell::new(Instant::<Timer>::new(start));
+        let clock = WrappingClock::from_embedded_time(|| timer.get());
+        assert_eq!(clock.tick_period(), (1, 32_768));
+
+        // step across the overflow, reading often enough to keep up
+        for ticks in (0..=114_000_u32).step_by(1000) {
+            timer.set(Instant::new(start.wrapping_add(ticks)));
+            assert_eq!(clock.ticks(), u64::from(ticks));
+        }
+        assert_eq!(clock.elapsed(), Duration::from_nanos(3_479_003_906));
+    }
+
+    #[test]
+    fn fractional_seconds() {
+        let timer = Cell::new(u32::MAX - 10);
+        let clock = WrappingClock::new(3, || timer.get());
+
+        timer.set(timer.get().wrapping_add(17));
+        assert_eq!(clock.elapsed(), Duration::new(5, 666_666_666));
+    }
+
+    #[test]
+    fn whole_nanosecond_ticks_skip_the_slow_path() {
+        let timer = Cell::new(0_u64);
+        let fast = WrappingClock::new(1_000_000, || timer.get());
+        let slow = WrappingClock::new(32_768, || timer.get());
+        assert_eq!(fast.nanos_per_tick, Some(1000));
+        assert_eq!(slow.nanos_per_tick, None);
+
+        timer.set(123_456_789);
+        assert_eq!(fast.elapsed(), Duration::from_micros(123_456_789));
+        assert_eq!(slow.elapsed(), Duration::new(3767, 602_203_369));
+
+        // overflowing the 64-bit nanosecond count still works
+        let huge = u64::MAX / 10;
+        timer.set(huge);
+        assert_eq!(
+            fast.elapsed(),
+            Duration::new(huge / 1_000_000, (huge % 1_000_000) as u32 * 1000)
+        );
+    }
+}
@@ -9,14 +9,33 @@ pub trait Device {
     type Error;
 
     fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error>;
+
+    /// Energise the motor's outputs so it is ready to take a step.
+    ///
+    /// The default implementation does nothing.
+    fn enable_outputs(&mut self) -> Result<(), Self::Error> { Ok(()) }
+
+    /// De-energise the motor's outputs so it doesn't draw current (and heat
+    /// up) while sitting idle.
+    ///
+    /// The default implementation does nothing.
+    fn disable_outputs(&mut self) -> Result<(), Self::Error> { Ok(()) }
 }
 
-impl<'a, D: Device> Device for &'a mut D {
+impl<D: Device> Device for &mut D {
     type Error = D::Error;
 
     fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
         (*self).step(ctx)
     }
+
+    fn enable_outputs(&mut self) -> Result<(), Self::Error> {
+        (*self).enable_outputs()
+    }
+
+    fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+        (*self).disable_outputs()
+    }
 }
 
 /// Extra contextual information passed to a [`Device`] when its
@@ -25,6 +44,8 @@ impl<'a, D: Device> Device for &'a mut D {
 pub struct StepContext {
     /// The new position, in steps.
     pub position: i64,
+    /// Is this step in the positive direction?
+    pub forward: bool,
     /// The time (as dictated by [`crate::SystemClock::elapsed()`]) this step
     /// was taken.
     pub step_time: Duration,
@@ -43,15 +64,10 @@ where
     F: FnMut() -> T,
     B: FnMut() -> T,
 {
-    Infallible {
-        forward,
-        backward,
-        previous_position: 0,
-    }
+    Infallible { forward, backward }
 }
 
 struct Infallible<F, B> {
-    previous_position: i64,
     forward: F,
     backward: B,
 }
@@ -65,15 +81,12 @@ where
 
     #[inline]
     fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
-        let diff = ctx.position - self.previous_position;
-
-        if diff > 0 {
+        if ctx.forward {
             (self.forward)();
-        } else if diff < 0 {
+        } else {
             (self.backward)();
         }
 
-        self.previous_position = ctx.position;
         Ok(())
     }
 }
@@ -89,15 +102,10 @@ where
     F: FnMut() -> Result<T, E>,
     B: FnMut() -> Result<T, E>,
 {
-    Fallible {
-        forward,
-        backward,
-        previous_position: 0,
-    }
+    Fallible { forward, backward }
 }
 
 struct Fallible<F, B> {
-    previous_position: i64,
     forward: F,
     backward: B,
 }
@@ -111,15 +119,12 @@ where
 
     #[inline]
     fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
-        let diff = ctx.position - self.previous_position;
-
-        if diff > 0 {
+        if ctx.forward {
             (self.forward)()?;
-        } else if diff < 0 {
+        } else {
             (self.backward)()?;
         }
 
-        self.previous_position = ctx.position;
         Ok(())
     }
 }
@@ -5,13 +5,13 @@ use crate::CummulativeSteps;
 use libm::F32Ext;
 
 use crate::{
-    utils::{Clamp, DurationHelpers},
-    Device, StepContext, SystemClock,
+    utils::{Clamp, DurationHelpers, Stopwatch},
+    Device, MotionProfile, StepContext, SystemClock,
 };
-use core::{f32::EPSILON, time::Duration};
+use core::time::Duration;
 
 /// A stepper motor driver.
-/// 
+///
 /// # Note
 ///
 /// You may want to use the [`CummulativeSteps`] helper to convert a
@@ -32,6 +32,19 @@ pub struct Driver {
     last_step_size: Duration,
     /// Min step size based on `max_speed`.
     min_step_size: Duration,
+
+    /// The maximum rate of change of acceleration, or `0.0` for trapezoidal
+    /// profiles.
+    jerk: f32,
+    /// The rate the speed is currently changing at, used by S-curve profiles.
+    current_acceleration: f32,
+    /// The speed we are ramping towards when in velocity mode, or `None` when
+    /// moving to a target position.
+    target_speed: Option<f32>,
+
+    /// How long to wait after the last step before disabling the outputs.
+    idle_timeout: Option<Duration>,
+    outputs_enabled: bool,
 }
 
 impl Driver {
@@ -48,9 +61,19 @@ impl Driver {
 
     /// Move to the specified location relative to the zero point (typically
     /// set when calibrating using [`Driver::set_current_position()`]).
+    ///
+    /// If the motor was running in velocity mode (see
+    /// [`Driver::set_target_speed()`]), it will switch back to positioning
+    /// mode without needing to stop first.
     #[inline]
     pub fn move_to(&mut self, location: i64) {
-        if self.target_position() != location {
+        let was_in_velocity_mode = self.target_speed.take().is_some();
+
+        if was_in_velocity_mode {
+            self.resume_position_mode();
+        }
+
+        if was_in_velocity_mode || self.target_position() != location {
             self.target_position = location;
             self.compute_new_speed();
         }
@@ -90,7 +113,7 @@ impl Driver {
 
         let acceleration = acceleration.abs();
 
-        if (self.acceleration - acceleration).abs() > EPSILON {
+        if (self.acceleration - acceleration).abs() > f32::EPSILON {
             // Recompute step_counter per Equation 17
             self.step_counter = (self.step_counter as f32 * self.acceleration
                 / acceleration) as i64;
@@ -108,6 +131,40 @@ impl Driver {
     #[inline]
     pub fn acceleration(&self) -> f32 { self.acceleration }
 
+    /// Set the jerk (the rate of change of acceleration, in
+    /// `steps/sec/sec/sec`).
+    ///
+    /// A non-zero jerk switches to an S-curve motion profile, where the
+    /// acceleration is ramped up and down smoothly instead of being switched
+    /// on and off at the corners of a trapezoid. This reduces vibration at the
+    /// cost of slightly longer moves. Setting the jerk to `0.0` (the default)
+    /// goes back to the trapezoidal profile.
+    #[inline]
+    pub fn set_jerk(&mut self, jerk: f32) {
+        debug_assert!(jerk >= 0.0);
+
+        let jerk = jerk.abs();
+        let was_s_curve = self.jerk > 0.0;
+        self.jerk = jerk;
+
+        if was_s_curve == (jerk > 0.0) {
+            return;
+        }
+
+        // Switching profiles mid-move, so carry on from our current speed
+        // instead of using state left over from the other profile
+        self.current_acceleration = 0.0;
+        if jerk > 0.0 {
+            self.step_counter = 0;
+        } else if self.target_speed.is_none() {
+            self.resume_position_mode();
+        }
+    }
+
+    /// Get the jerk.
+    #[inline]
+    pub fn jerk(&self) -> f32 { self.jerk }
+
     /// Set the desired constant speed in `steps/sec`.
     ///
     /// Speeds of more than 1000 steps per second are unreliable. Very slow
@@ -116,7 +173,7 @@ impl Driver {
     /// frequently you call the [`Driver::poll_at_constant_speed()`] method. The
     /// speed will be limited by the current value of [`Driver::max_speed()`].
     pub fn set_speed(&mut self, speed: f32) {
-        if (speed - self.speed).abs() < EPSILON {
+        if (speed - self.speed).abs() < f32::EPSILON {
             return;
         }
 
@@ -136,6 +193,34 @@ impl Driver {
     #[inline]
     pub fn speed(&self) -> f32 { self.speed }
 
+    /// Accelerate (or decelerate) to the desired speed in `steps/sec` and keep
+    /// going until told otherwise, for example when jogging an axis or
+    /// running a conveyor.
+    ///
+    /// Negative speeds move backwards, and if the sign changes the motor will
+    /// slow down and reverse through zero. All speed changes use the
+    /// configured acceleration. Calling [`Driver::move_to()`] or
+    /// [`Driver::stop()`] will switch back to positioning mode without needing
+    /// to stop first.
+    ///
+    /// The speed will be limited by the current value of
+    /// [`Driver::max_speed()`].
+    pub fn set_target_speed(&mut self, speed: f32) {
+        let speed = Clamp::clamp(speed, -self.max_speed, self.max_speed);
+
+        self.target_speed = Some(speed);
+        self.target_position = self.current_position;
+
+        if self.step_interval == Duration::new(0, 0) {
+            self.start_velocity_mode(speed);
+        }
+    }
+
+    /// The speed we are accelerating towards in velocity mode, or `None` when
+    /// moving to a target position.
+    #[inline]
+    pub fn target_speed(&self) -> Option<f32> { self.target_speed }
+
     /// Get the number of steps to go until reaching the target position.
     #[inline]
     pub fn distance_to_go(&self) -> i64 {
@@ -157,6 +242,8 @@ impl Driver {
         self.target_position = position;
         self.step_interval = Duration::new(0, 0);
         self.speed = 0.0;
+        self.current_acceleration = 0.0;
+        self.target_speed = None;
     }
 
     /// Get the current motor position, as measured by counting the number of
@@ -177,8 +264,15 @@ impl Driver {
             return;
         }
 
-        let stopping_distance =
-            (self.speed * self.speed) / (2.0 * self.acceleration);
+        let stopping_distance = if self.jerk > 0.0 {
+            Motion {
+                speed: self.speed.abs(),
+                acceleration: self.current_acceleration,
+            }
+            .stopping_distance(self.acceleration, self.jerk)
+        } else {
+            (self.speed * self.speed) / (2.0 * self.acceleration)
+        };
         let steps_to_stop = stopping_distance.round() as i64 + 1;
 
         if self.speed > 0.0 {
@@ -188,6 +282,65 @@ impl Driver {
         }
     }
 
+    /// Predict how a move to `target` would play out, starting from the current
+    /// position and speed.
+    ///
+    /// This uses the closed-form equations for a trapezoidal (or triangular)
+    /// profile, so it is cheap to calculate but ignores the jerk limit (see
+    /// [`Driver::set_jerk()`]) and the small rounding errors introduced by
+    /// stepping.
+    pub fn predict_move(&self, target: i64) -> MotionProfile {
+        let distance = target - self.current_position();
+
+        MotionProfile::new(
+            distance as f32,
+            self.speed,
+            self.max_speed,
+            self.acceleration,
+        )
+    }
+
+    /// Automatically disable the [`Device`]'s outputs once the motor has been
+    /// idle for a certain amount of time, or `None` to leave them energised.
+    ///
+    /// The outputs will be re-enabled before the next step is taken.
+    #[inline]
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Get the idle timeout.
+    #[inline]
+    pub fn idle_timeout(&self) -> Option<Duration> { self.idle_timeout }
+
+    /// Are the [`Device`]'s outputs currently enabled?
+    ///
+    /// Outputs are enabled automatically before taking a step.
+    #[inline]
+    pub fn outputs_enabled(&self) -> bool { self.outputs_enabled }
+
+    /// Enable the [`Device`]'s outputs (see [`Device::enable_outputs()`]).
+    pub fn enable_outputs<D: Device>(
+        &mut self,
+        mut device: D,
+    ) -> Result<(), D::Error> {
+        device.enable_outputs()?;
+        self.outputs_enabled = true;
+
+        Ok(())
+    }
+
+    /// Disable the [`Device`]'s outputs (see [`Device::disable_outputs()`]).
+    pub fn disable_outputs<D: Device>(
+        &mut self,
+        mut device: D,
+    ) -> Result<(), D::Error> {
+        device.disable_outputs()?;
+        self.outputs_enabled = false;
+
+        Ok(())
+    }
+
     /// Checks to see if the motor is currently running to a target.
     #[inline]
     pub fn is_running(&self) -> bool {
@@ -195,6 +348,23 @@ impl Driver {
     }
 
     fn compute_new_speed(&mut self) {
+        if let Some(target_speed) = self.target_speed {
+            // like S-curves, the speed is only updated after each step
+            if self.step_interval == Duration::new(0, 0) {
+                self.start_velocity_mode(target_speed);
+            }
+            return;
+        }
+
+        if self.jerk > 0.0 {
+            // S-curves are only updated after each step, so a new target is
+            // picked up when the next step is taken
+            if self.step_interval == Duration::new(0, 0) {
+                self.start_s_curve();
+            }
+            return;
+        }
+
         let distance_to = self.distance_to_go();
         let distance_to_stop =
             (self.speed() * self.speed()) / (2.0 * self.acceleration());
@@ -208,18 +378,21 @@ impl Driver {
             return;
         }
 
+        // the direction we are currently moving in
+        let mut forward = self.speed > 0.0;
+
         if distance_to > 0 {
             // the target is in front of us
             // We need to go forwards, maybe decelerate now?
             if self.step_counter > 0 {
                 // Currently accelerating, need to decel now? Or maybe going the
                 // wrong way?
-                if steps_to_stop >= distance_to || distance_to < 0 {
+                if steps_to_stop >= distance_to || !forward {
                     self.step_counter = -steps_to_stop; // start decelerating
                 }
             } else if self.step_counter < 0 {
                 // Currently decelerating, need to accel again?
-                if steps_to_stop < distance_to && distance_to > 0 {
+                if steps_to_stop < distance_to && forward {
                     self.step_counter = -self.step_counter; // start accelerating
                 }
             }
@@ -229,12 +402,12 @@ impl Driver {
             if self.step_counter > 0 {
                 // Currently accelerating, need to decel now? Or maybe going the
                 // wrong way?
-                if steps_to_stop >= -distance_to || distance_to > 0 {
+                if steps_to_stop >= -distance_to || forward {
                     self.step_counter = -steps_to_stop;
                 }
             } else if self.step_counter < 0 {
                 // currently decelerating, need to accel again?
-                if steps_to_stop < -distance_to && distance_to < 0 {
+                if steps_to_stop < -distance_to && !forward {
                     self.step_counter = -self.step_counter;
                 }
             }
@@ -243,6 +416,7 @@ impl Driver {
         if self.step_counter == 0 {
             // This is the first step after having stopped
             self.last_step_size = self.initial_step_size;
+            forward = distance_to > 0;
         } else {
             // Subsequent step. Works for accel (n is +_ve) and decel (n is
             // -ve).
@@ -260,11 +434,233 @@ impl Driver {
         self.step_interval = self.last_step_size;
         self.speed = self.last_step_size.as_secs_f32_2().recip();
 
-        if distance_to < 0 {
+        if !forward {
             self.speed *= -1.0;
         }
     }
 
+    /// Calculate the speed after a step has been taken.
+    fn compute_speed_after_step(&mut self) {
+        if let Some(target_speed) = self.target_speed {
+            self.compute_new_velocity_mode_speed(target_speed);
+        } else if self.jerk > 0.0 {
+            self.compute_new_s_curve_speed();
+        } else {
+            self.compute_new_speed();
+        }
+    }
+
+    /// Start moving at the target speed from a standstill.
+    fn start_velocity_mode(&mut self, target_speed: f32) {
+        // The first step uses the same initial step size as positioning
+        // mode, unless we're asked to go even slower
+        let initial_speed = self.initial_step_size.as_secs_f32_2().recip();
+        let speed = initial_speed.min(target_speed.abs());
+
+        if target_speed < 0.0 {
+            self.set_velocity_mode_speed(-speed);
+        } else {
+            self.set_velocity_mode_speed(speed);
+        }
+    }
+
+    /// Ramp the speed towards the target speed after taking a step.
+    ///
+    /// Moving one step at constant acceleration changes the square of the
+    /// speed by `2 * acceleration`, so we can calculate the new speed directly
+    /// instead of using the step counter recurrence.
+    fn compute_new_velocity_mode_speed(&mut self, target_speed: f32) {
+        // there's no target position in velocity mode
+        self.target_position = self.current_position;
+
+        let speed = self.speed;
+        let speed_squared = speed * speed;
+        let forward = speed > 0.0;
+        let same_direction =
+            forward == (target_speed > 0.0) && target_speed != 0.0;
+        let signed = |s: f32| if forward { s } else { -s };
+
+        if speed == 0.0 {
+            self.start_velocity_mode(target_speed);
+        } else if same_direction && speed.abs() <= target_speed.abs() {
+            // speed up (or hold our current speed)
+            let faster = (speed_squared + 2.0 * self.acceleration).sqrt();
+            let new_speed = faster.min(target_speed.abs());
+            self.set_velocity_mode_speed(signed(new_speed));
+        } else if same_direction {
+            // slow down without dropping below the target speed
+            let slower = speed_squared - 2.0 * self.acceleration;
+            let new_speed = slower.max(target_speed * target_speed).sqrt();
+            self.set_velocity_mode_speed(signed(new_speed));
+        } else {
+            // slow down so we can stop or reverse
+            let slower = speed_squared - 2.0 * self.acceleration;
+
+            if slower > 0.0 {
+                self.set_velocity_mode_speed(signed(slower.sqrt()));
+            } else {
+                // we've come to a stop, start again from zero (possibly in the
+                // other direction)
+                self.set_velocity_mode_speed(0.0);
+                self.start_velocity_mode(target_speed);
+            }
+        }
+    }
+
+    fn set_velocity_mode_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        self.current_acceleration = 0.0;
+
+        if speed == 0.0 {
+            self.step_interval = Duration::new(0, 0);
+        } else {
+            self.step_interval = Duration::from_secs_f32_2(speed.abs().recip());
+        }
+    }
+
+    /// Set up the step counter recurrence so we can continue from the current
+    /// speed in positioning mode.
+    fn resume_position_mode(&mut self) {
+        if self.speed == 0.0 {
+            self.step_counter = 0;
+            return;
+        }
+
+        // Equation 16, the number of steps it would take to reach this speed
+        let steps = (self.speed * self.speed) / (2.0 * self.acceleration);
+        self.step_counter = (steps.round() as i64).max(1);
+        self.last_step_size = self.step_interval;
+    }
+
+    /// Start moving towards the target from a standstill.
+    fn start_s_curve(&mut self) {
+        let distance_to = self.distance_to_go();
+        if distance_to == 0 {
+            return;
+        }
+
+        if let Some((step_time, motion)) =
+            Motion::AT_REST.time_to_next_step(self.acceleration, self.jerk)
+        {
+            self.set_s_curve_step(distance_to > 0, step_time, motion);
+        }
+    }
+
+    /// The S-curve equivalent of [`Driver::compute_new_speed()`].
+    ///
+    /// Instead of the step counter recurrence, we keep track of the current
+    /// acceleration and ramp it towards `+acceleration`, `0`, or
+    /// `-acceleration` at the configured jerk. Before each step we look ahead
+    /// to make sure we'll still be able to come to a smooth stop at the
+    /// target, so retargeting mid-move works the same as with trapezoidal
+    /// profiles.
+    fn compute_new_s_curve_speed(&mut self) {
+        let distance_to = self.distance_to_go() as f32;
+        let forward = self.speed > 0.0;
+        // how far the target is in the direction we are currently moving
+        let remaining = if forward { distance_to } else { -distance_to };
+        let motion = Motion {
+            speed: self.speed.abs(),
+            acceleration: self.current_acceleration,
+        };
+
+        if remaining <= 0.0
+            && (motion.stopping_distance(self.acceleration, self.jerk) < 1.0
+                || motion.speed <= self.s_curve_creep_speed())
+        {
+            // We are at (or past) the target and slow enough to stop
+            self.stop_s_curve();
+            return;
+        }
+
+        let next = if remaining > 0.0 {
+            self.plan_s_curve_step(motion, remaining)
+        } else {
+            // we've overshot, slow down as quickly as possible
+            motion.time_to_next_step(-self.acceleration, self.jerk)
+        };
+
+        match next {
+            Some((step_time, motion)) => {
+                self.set_s_curve_step(forward, step_time, motion)
+            },
+            // we'll come to a stop before reaching the next step
+            None => self.stop_s_curve(),
+        }
+    }
+
+    /// Pick the most aggressive acceleration which still lets us stop before
+    /// overshooting the target after taking the next step.
+    fn plan_s_curve_step(
+        &self,
+        motion: Motion,
+        remaining: f32,
+    ) -> Option<(f32, Motion)> {
+        // ease off as we approach max speed so we get there with zero
+        // acceleration
+        let speed_up = match motion
+            .time_to_next_step(self.acceleration, self.jerk)
+        {
+            Some((_, next)) if next.peak_speed(self.jerk) < self.max_speed => {
+                self.acceleration
+            },
+            _ => 0.0,
+        };
+
+        let mut fallback = None;
+
+        for &target_acceleration in &[speed_up, 0.0, -self.acceleration] {
+            if let Some((step_time, next)) =
+                motion.time_to_next_step(target_acceleration, self.jerk)
+            {
+                let stopping_distance =
+                    next.stopping_distance(self.acceleration, self.jerk);
+                if stopping_distance <= remaining - 1.0 {
+                    return Some((step_time, next));
+                }
+
+                // otherwise, brake as hard as possible without stalling
+                fallback = Some((step_time, next));
+            }
+        }
+
+        fallback
+    }
+
+    /// The speed after taking a single step from a standstill. We can stop
+    /// from this speed as abruptly as we would at the end of a one-step move.
+    fn s_curve_creep_speed(&self) -> f32 {
+        Motion::AT_REST
+            .time_to_next_step(self.acceleration, self.jerk)
+            .map(|(_, motion)| motion.speed)
+            .unwrap_or(0.0)
+    }
+
+    fn set_s_curve_step(
+        &mut self,
+        forward: bool,
+        step_time: f32,
+        mut motion: Motion,
+    ) {
+        if motion.speed > self.max_speed {
+            motion.speed = self.max_speed;
+            motion.acceleration = motion.acceleration.min(0.0);
+        }
+
+        self.step_interval = Duration::from_secs_f32_2(step_time);
+        self.current_acceleration = motion.acceleration;
+        self.speed = if forward { motion.speed } else { -motion.speed };
+    }
+
+    fn stop_s_curve(&mut self) {
+        self.step_interval = Duration::new(0, 0);
+        self.speed = 0.0;
+        self.current_acceleration = 0.0;
+
+        // we may need to turn around and head back to the target
+        self.start_s_curve();
+    }
+
     /// Poll the driver and step it if a step is due.
     ///
     /// This function must called as frequently as possoble, but at least once
@@ -274,19 +670,58 @@ impl Driver {
     /// and then only when a step is due, based on the current speed and the
     /// time since the last step.
     ///
+    /// If an idle timeout has been set (see [`Driver::set_idle_timeout()`]),
+    /// this is also where the [`Device`]'s outputs will be disabled.
+    ///
     /// # Warning
     ///
     /// For correctness, the same [`SystemClock`] should be used every time
     /// [`Driver::poll()`] is called. Failing to do so may mess up internal
     /// timing calculations.
     #[inline]
-    pub fn poll<C, D>(&mut self, device: D, clock: C) -> Result<(), D::Error>
+    pub fn poll<C, D>(
+        &mut self,
+        mut device: D,
+        clock: C,
+    ) -> Result<(), D::Error>
     where
         C: SystemClock,
         D: Device,
     {
-        if self.poll_at_constant_speed(device, clock)? {
-            self.compute_new_speed();
+        if self.poll_at_constant_speed(&mut device, &clock)? {
+            self.compute_speed_after_step();
+        } else {
+            self.disable_outputs_when_idle(device, clock)?;
+        }
+
+        Ok(())
+    }
+
+    fn disable_outputs_when_idle<C, D>(
+        &mut self,
+        device: D,
+        clock: C,
+    ) -> Result<(), D::Error>
+    where
+        C: SystemClock,
+        D: Device,
+    {
+        let timeout = match self.idle_timeout {
+            Some(timeout) if self.outputs_enabled && !self.is_running() => {
+                timeout
+            },
+            _ => return Ok(()),
+        };
+
+        let now = clock.elapsed();
+
+        if now < self.last_step_time {
+            // the clock went backwards, so start timing from here
+            self.last_step_time = now;
+        }
+
+        if now - self.last_step_time >= timeout {
+            self.disable_outputs(device)?;
         }
 
         Ok(())
@@ -295,12 +730,58 @@ impl Driver {
     /// Poll the motor and step it if a step is due, implementing a constant
     /// speed as set by the most recent call to [`Driver::set_speed()`].
     ///
+    /// The direction is determined by the sign of [`Driver::speed()`], so this
+    /// will happily keep going past the target position (see
+    /// [`Driver::poll_speed_to_position()`] if you want to stop there).
+    ///
     /// You must call this as frequently as possible, but at least once per step
     /// interval, returns true if the motor was stepped.
     pub fn poll_at_constant_speed<C, D>(
         &mut self,
-        mut device: D,
+        device: D,
+        clock: C,
+    ) -> Result<bool, D::Error>
+    where
+        C: SystemClock,
+        D: Device,
+    {
+        let forward = self.speed > 0.0;
+        self.step_if_due(device, clock, forward)
+    }
+
+    /// Poll the motor and step it towards the target position at the constant
+    /// speed set by [`Driver::set_speed()`], without any acceleration.
+    ///
+    /// This is the equivalent of `AccelStepper::runSpeedToPosition()`. The
+    /// motor always moves towards the target, regardless of the sign of
+    /// [`Driver::speed()`], and no more steps are taken once it has been
+    /// reached.
+    ///
+    /// You must call this as frequently as possible, but at least once per step
+    /// interval, returns true if the motor was stepped.
+    pub fn poll_speed_to_position<C, D>(
+        &mut self,
+        device: D,
+        clock: C,
+    ) -> Result<bool, D::Error>
+    where
+        C: SystemClock,
+        D: Device,
+    {
+        let distance_to = self.distance_to_go();
+
+        if distance_to == 0 {
+            return Ok(false);
+        }
+
+        self.step_if_due(device, clock, distance_to > 0)
+    }
+
+    fn step_if_due<C, D>(
+        &mut self,
+        device: D,
         clock: C,
+        forward: bool,
     ) -> Result<bool, D::Error>
     where
         C: SystemClock,
@@ -313,67 +794,541 @@ impl Driver {
 
         let now = clock.elapsed();
 
+        if now < self.last_step_time {
+            // the clock went backwards (e.g. a hardware timer wrapped
+            // around), so start timing the next step from here
+            self.last_step_time = now;
+        }
+
         if now - self.last_step_time >= self.step_interval {
             // we need to take a step
-
-            // Note: we can't assign to current_position directly because we
-            // a failed step shouldn't update any internal state
-            let new_position = if self.distance_to_go() > 0 {
-                self.current_position + 1
-            } else {
-                self.current_position - 1
-            };
-
-            let ctx = StepContext {
-                position: new_position,
-                step_time: now,
-            };
-            device.step(&ctx)?;
-
-            self.current_position = new_position;
-            self.last_step_time = now; // Caution: does not account for costs in step()
-
+            self.take_step(device, now, forward)?;
             Ok(true)
         } else {
             Ok(false)
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::cell::Cell;
+    /// Block until the motor reaches its target position, repeatedly calling
+    /// [`Driver::poll()`].
+    ///
+    /// The `should_abort` callback is invoked between polls and can be used to
+    /// cancel the move early (e.g. when an emergency stop button is pressed),
+    /// while the optional `timeout` puts an upper bound on how long we'll
+    /// wait. In both cases the [`Driver`] is left as-is, so you may want to
+    /// call [`Driver::stop()`] and keep polling to bring the motor to a
+    /// controlled stop.
+    pub fn run_to_position<C, D, F>(
+        &mut self,
+        mut device: D,
+        clock: C,
+        timeout: Option<Duration>,
+        should_abort: F,
+    ) -> Result<RunOutcome, D::Error>
+    where
+        C: SystemClock,
+        D: Device,
+        F: FnMut() -> bool,
+    {
+        run_until_stopped(clock, timeout, should_abort, |clock| {
+            self.poll(&mut device, clock)?;
+            Ok(self.is_running())
+        })
+    }
 
-    #[derive(Debug, Copy, Clone, PartialEq, Default)]
-    struct NopDevice;
+    /// Block until the motor reaches its target position, stepping at the
+    /// constant speed set by [`Driver::set_speed()`] (see
+    /// [`Driver::poll_speed_to_position()`]).
+    ///
+    /// This is the equivalent of `AccelStepper::runSpeedToPosition()` when
+    /// called in a loop. Remember that [`Driver::move_to()`] recalculates the
+    /// speed, so [`Driver::set_speed()`] should be called afterwards. The
+    /// timeout and `should_abort` callback work the same as with
+    /// [`Driver::run_to_position()`].
+    pub fn run_speed_to_position<C, D, F>(
+        &mut self,
+        mut device: D,
+        clock: C,
+        timeout: Option<Duration>,
+        should_abort: F,
+    ) -> Result<RunOutcome, D::Error>
+    where
+        C: SystemClock,
+        D: Device,
+        F: FnMut() -> bool,
+    {
+        run_until_stopped(clock, timeout, should_abort, |clock| {
+            self.poll_speed_to_position(&mut device, clock)?;
+            Ok(self.distance_to_go() != 0)
+        })
+    }
 
-    impl Device for NopDevice {
-        type Error = ();
+    /// Move to a new position and block until it is reached (see
+    /// [`Driver::run_to_position()`]).
+    pub fn run_to_new_position<C, D, F>(
+        &mut self,
+        position: i64,
+        device: D,
+        clock: C,
+        timeout: Option<Duration>,
+        should_abort: F,
+    ) -> Result<RunOutcome, D::Error>
+    where
+        C: SystemClock,
+        D: Device,
+        F: FnMut() -> bool,
+    {
+        self.move_to(position);
+        self.run_to_position(device, clock, timeout, should_abort)
+    }
 
-        fn step(&mut self, _ctx: &StepContext) -> Result<(), Self::Error> {
-            Ok(())
+    /// Get the time (as dictated by [`SystemClock::elapsed()`]) at which the
+    /// next step is due, or `None` if the motor isn't moving.
+    ///
+    /// This can be used to program a hardware timer's compare register, so
+    /// [`Driver::fire_step()`] is called from an interrupt instead of busy
+    /// polling.
+    #[inline]
+    pub fn next_step_time(&self) -> Option<Duration> {
+        if self.step_interval == Duration::new(0, 0) {
+            None
+        } else {
+            Some(self.last_step_time + self.step_interval)
         }
     }
 
-    #[derive(Debug, Default)]
-    struct DummyClock {
-        ticks: Cell<u32>,
-    }
+    /// Take the next step immediately, regardless of whether it is due, then
+    /// calculate the new speed.
+    ///
+    /// This is intended to be called from a timer interrupt which was set to
+    /// fire at [`Driver::next_step_time()`]. The `now` parameter is recorded as
+    /// the time the step was taken, so passing in the scheduled deadline
+    /// (instead of reading the clock) means interrupt latency won't accumulate
+    /// as timing errors.
+    ///
+    /// Returns `false` if the motor isn't moving and no step was taken.
+    pub fn fire_step<D: Device>(
+        &mut self,
+        device: D,
+        now: Duration,
+    ) -> Result<bool, D::Error> {
+        if self.step_interval == Duration::new(0, 0) {
+            return Ok(false);
+        }
 
-    impl SystemClock for DummyClock {
-        fn elapsed(&self) -> Duration {
-            let ticks = self.ticks.get();
-            self.ticks.set(ticks + 1);
+        let forward = self.speed > 0.0;
+        self.take_step(device, now, forward)?;
+        self.compute_speed_after_step();
 
-            Duration::new(ticks as u64, 0)
-        }
+        Ok(true)
     }
 
-    #[test]
-    fn compute_new_speeds_when_already_at_target() {
-        let mut driver = Driver::default();
-        driver.target_position = driver.current_position;
+    /// Set the target position without planning a move, for when something
+    /// else (e.g. a [`crate::MultiDriver`]) decides when each step is taken.
+    pub(crate) fn set_externally_driven_target(&mut self, position: i64) {
+        self.target_position = position;
+        self.step_interval = Duration::new(0, 0);
+        self.speed = 0.0;
+        self.step_counter = 0;
+        self.current_acceleration = 0.0;
+        self.target_speed = None;
+    }
+
+    pub(crate) fn take_step<D: Device>(
+        &mut self,
+        mut device: D,
+        now: Duration,
+        forward: bool,
+    ) -> Result<(), D::Error> {
+        // Note: we can't assign to current_position directly because we
+        // a failed step shouldn't update any internal state
+        let new_position = if forward {
+            self.current_position + 1
+        } else {
+            self.current_position - 1
+        };
+
+        if !self.outputs_enabled {
+            self.enable_outputs(&mut device)?;
+        }
+
+        let ctx = StepContext {
+            position: new_position,
+            forward,
+            step_time: now,
+        };
+        device.step(&ctx)?;
+
+        self.current_position = new_position;
+        self.last_step_time = now; // Caution: does not account for costs in step()
+
+        Ok(())
+    }
+}
+
+/// How a blocking move (e.g. [`Driver::run_to_position()`]) finished.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RunOutcome {
+    /// The motor reached its target position.
+    Reached,
+    /// The timeout elapsed before the target was reached.
+    TimedOut,
+    /// The move was cancelled by the caller.
+    Aborted,
+}
+
+/// Keep polling until the motor stops running, checking for cancellation and
+/// timeouts along the way.
+///
+/// The `poll` closure should return whether the motor is still running.
+pub(crate) fn run_until_stopped<C, F, P, E>(
+    clock: C,
+    timeout: Option<Duration>,
+    mut should_abort: F,
+    mut poll: P,
+) -> Result<RunOutcome, E>
+where
+    C: SystemClock,
+    F: FnMut() -> bool,
+    P: FnMut(&C) -> Result<bool, E>,
+{
+    let mut stopwatch = timeout
+        .map(|_| Stopwatch::started(clock.elapsed(), Duration::new(0, 0)));
+
+    loop {
+        if !poll(&clock)? {
+            return Ok(RunOutcome::Reached);
+        }
+
+        if should_abort() {
+            return Ok(RunOutcome::Aborted);
+        }
+
+        if let (Some(timeout), Some(stopwatch)) = (timeout, &mut stopwatch) {
+            if stopwatch.elapsed(clock.elapsed()) >= timeout {
+                return Ok(RunOutcome::TimedOut);
+            }
+        }
+    }
+}
+
+/// The instantaneous speed and acceleration while following an S-curve.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Motion {
+    speed: f32,
+    acceleration: f32,
+}
+
+impl Motion {
+    const AT_REST: Motion = Motion {
+        speed: 0.0,
+        acceleration: 0.0,
+    };
+
+    /// The distance travelled while coming to a smooth stop.
+    ///
+    /// Any positive acceleration is first ramped down to zero, then we follow
+    /// a symmetric S-curve (ramp the deceleration up, hold it at
+    /// `max_acceleration`, then ramp it back down) to a standstill.
+    fn stopping_distance(self, max_acceleration: f32, jerk: f32) -> f32 {
+        let Motion {
+            speed,
+            acceleration,
+        } = self;
+
+        if acceleration > 0.0 {
+            let t = acceleration / jerk;
+            let ramp_down =
+                speed * t + acceleration * t * t / 2.0 - jerk * t * t * t / 6.0;
+            let speed = speed + acceleration * acceleration / (2.0 * jerk);
+
+            ramp_down + braking_distance(speed, max_acceleration, jerk)
+        } else if acceleration < 0.0 {
+            // We're already part way through braking, so work out the speed
+            // we would have started braking at and subtract the distance
+            // covered while ramping up the deceleration.
+            let t = -acceleration / jerk;
+            let initial_speed =
+                speed + acceleration * acceleration / (2.0 * jerk);
+            let covered = initial_speed * t - jerk * t * t * t / 6.0;
+
+            (braking_distance(initial_speed, max_acceleration, jerk) - covered)
+                .max(0.0)
+        } else {
+            braking_distance(speed, max_acceleration, jerk)
+        }
+    }
+
+    /// How fast we'll be going once the acceleration has been ramped down to
+    /// zero.
+    fn peak_speed(self, jerk: f32) -> f32 {
+        let a = self.acceleration.max(0.0);
+        self.speed + a * a / (2.0 * jerk)
+    }
+
+    /// Where we'll be after `t` seconds, returning the distance travelled and
+    /// the new [`Motion`].
+    fn after(
+        self,
+        t: f32,
+        target_acceleration: f32,
+        jerk: f32,
+    ) -> (f32, Motion) {
+        let difference = target_acceleration - self.acceleration;
+        let jerk = if difference < 0.0 { -jerk } else { jerk };
+
+        // ramp the acceleration towards the target...
+        let ramp = (difference / jerk).min(t);
+        let distance = self.speed * ramp
+            + self.acceleration * ramp * ramp / 2.0
+            + jerk * ramp * ramp * ramp / 6.0;
+        let speed =
+            self.speed + self.acceleration * ramp + jerk * ramp * ramp / 2.0;
+        let acceleration = self.acceleration + jerk * ramp;
+
+        // ... then hold it
+        let hold = t - ramp;
+        let motion = Motion {
+            speed: speed + acceleration * hold,
+            acceleration,
+        };
+
+        (
+            distance + speed * hold + acceleration * hold * hold / 2.0,
+            motion,
+        )
+    }
+
+    /// How long it'll take to travel one step, or `None` if we'll stop first.
+    ///
+    /// The acceleration is ramped towards the target and then held, so we
+    /// work out where the ramp ends and solve whichever phase the step lands
+    /// in directly.
+    fn time_to_next_step(
+        self,
+        target_acceleration: f32,
+        jerk: f32,
+    ) -> Option<(f32, Motion)> {
+        let difference = target_acceleration - self.acceleration;
+        let signed_jerk = if difference < 0.0 { -jerk } else { jerk };
+        let mut ramp = difference / signed_jerk;
+
+        // we can't take a step if we come to a halt part way through the ramp
+        let stopped_during_ramp = match time_until_stopped(
+            self.speed,
+            self.acceleration,
+            signed_jerk,
+        ) {
+            Some(stop) if stop < ramp => {
+                ramp = stop;
+                true
+            },
+            _ => false,
+        };
+
+        let (ramp_distance, end_of_ramp) =
+            self.after(ramp, target_acceleration, jerk);
+
+        let step_time = if ramp_distance >= 1.0 {
+            // the step is taken while the acceleration is still changing
+            self.solve_ramp(signed_jerk, ramp)
+        } else if stopped_during_ramp {
+            return None;
+        } else {
+            // the step is taken while the acceleration is held constant
+            ramp + time_to_travel(
+                end_of_ramp.speed,
+                end_of_ramp.acceleration,
+                1.0 - ramp_distance,
+            )?
+        };
+
+        Some((
+            step_time,
+            self.after(step_time, target_acceleration, jerk).1,
+        ))
+    }
+
+    /// Find when we'll have travelled one step, given that it happens within
+    /// the first `ramp` seconds while the acceleration changes at `jerk`.
+    ///
+    /// The distance is a cubic in time whose derivative (our speed) is known,
+    /// so a few Newton iterations starting from the constant-acceleration
+    /// solution are enough to converge. We fall back to bisection whenever
+    /// Newton's method would leave the bracket.
+    fn solve_ramp(self, jerk: f32, ramp: f32) -> f32 {
+        let Motion {
+            speed,
+            acceleration,
+        } = self;
+        let distance = |t: f32| {
+            speed * t + acceleration * t * t / 2.0 + jerk * t * t * t / 6.0
+        };
+
+        let mut lower: f32 = 0.0;
+        let mut upper = ramp;
+        let mut t = match time_to_travel(speed, acceleration, 1.0) {
+            Some(t) if t < ramp => t,
+            // we start from a standstill, or the jerk dominates
+            _ if speed == 0.0 && acceleration == 0.0 => {
+                (6.0 / jerk).cbrt().min(ramp)
+            },
+            _ => ramp,
+        };
+
+        for _ in 0..8 {
+            let error = distance(t) - 1.0;
+            if error == 0.0 {
+                break;
+            } else if error > 0.0 {
+                upper = t;
+            } else {
+                lower = t;
+            }
+
+            let speed_at_t = speed + acceleration * t + jerk * t * t / 2.0;
+            let next = t - error / speed_at_t;
+            let next = if next >= lower && next <= upper {
+                next
+            } else {
+                (lower + upper) / 2.0
+            };
+
+            let converged = (next - t).abs() <= t * f32::EPSILON;
+            t = next;
+            if converged {
+                break;
+            }
+        }
+
+        t
+    }
+}
+
+/// When the speed will first drop to zero if the acceleration changes at a
+/// constant `jerk`, if ever.
+fn time_until_stopped(speed: f32, acceleration: f32, jerk: f32) -> Option<f32> {
+    // solve speed + acceleration * t + jerk * t^2 / 2 = 0
+    if jerk == 0.0 {
+        return if acceleration < 0.0 {
+            Some(-speed / acceleration)
+        } else {
+            None
+        };
+    }
+
+    let discriminant = acceleration * acceleration - 2.0 * jerk * speed;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    let mut roots =
+        [(-acceleration - root) / jerk, (-acceleration + root) / jerk];
+    if roots[0] > roots[1] {
+        roots.swap(0, 1);
+    }
+
+    roots.iter().copied().find(|&t| t > 0.0)
+}
+
+/// How long it takes to travel `distance` from `speed` at a constant
+/// `acceleration`, or `None` if we'd stop first.
+fn time_to_travel(speed: f32, acceleration: f32, distance: f32) -> Option<f32> {
+    if acceleration == 0.0 {
+        return if speed > 0.0 {
+            Some(distance / speed)
+        } else {
+            None
+        };
+    }
+
+    // solve speed * t + acceleration * t^2 / 2 = distance, using the form
+    // which avoids cancellation when the acceleration is small
+    let discriminant = speed * speed + 2.0 * acceleration * distance;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let denominator = speed + discriminant.sqrt();
+    if denominator > 0.0 {
+        Some(2.0 * distance / denominator)
+    } else {
+        None
+    }
+}
+
+/// The distance needed to stop from `speed` with zero initial acceleration.
+fn braking_distance(speed: f32, max_acceleration: f32, jerk: f32) -> f32 {
+    let duration = if speed * jerk >= max_acceleration * max_acceleration {
+        // ramp up, hold at max deceleration, ramp down
+        speed / max_acceleration + max_acceleration / jerk
+    } else {
+        // never reaches max deceleration
+        2.0 * (speed / jerk).sqrt()
+    };
+
+    // the profile is symmetric, so the average speed is half the initial
+    speed * duration / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support::Recorder, ManualClock};
+    use std::cell::Cell;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Default)]
+    struct NopDevice;
+
+    impl Device for NopDevice {
+        type Error = ();
+
+        fn step(&mut self, _ctx: &StepContext) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// A millisecond clock which wraps back to zero every second.
+    #[derive(Debug, Default)]
+    struct WrappingMillis {
+        ticks: Cell<u64>,
+    }
+
+    impl SystemClock for WrappingMillis {
+        fn elapsed(&self) -> Duration {
+            let ticks = self.ticks.get() + 1;
+            self.ticks.set(ticks);
+
+            Duration::from_millis(ticks % 1000)
+        }
+    }
+
+    #[test]
+    fn recover_when_the_clock_goes_backwards() {
+        let mut steps = 0;
+        let clock = WrappingMillis::default();
+
+        {
+            let mut dev = crate::func_device(|| steps += 1, || {});
+            let mut driver = Driver::new();
+            driver.set_max_speed(100.0);
+            driver.set_speed(100.0);
+
+            for _ in 0..3000 {
+                driver.poll_at_constant_speed(&mut dev, &clock).unwrap();
+            }
+        }
+
+        // 3 seconds at 100 steps/second, losing at most one step each time
+        // the clock wraps around
+        assert!((297..=300).contains(&steps), "{}", steps);
+    }
+
+    #[test]
+    fn compute_new_speeds_when_already_at_target() {
+        let mut driver = Driver::default();
+        driver.target_position = driver.current_position;
 
         driver.compute_new_speed();
 
@@ -385,7 +1340,7 @@ mod tests {
     fn dont_step_when_already_at_target() {
         let mut forward = 0;
         let mut back = 0;
-        let clock = DummyClock::default();
+        let clock = ManualClock::with_auto_advance(Duration::from_secs(1));
 
         {
             let mut dev = crate::func_device(|| forward += 1, || back += 1);
@@ -400,4 +1355,744 @@ mod tests {
         assert_eq!(forward, 0);
         assert_eq!(back, 0);
     }
+
+    #[derive(Debug, Default)]
+    struct OutputTrackingDevice {
+        enabled: bool,
+        steps_while_disabled: usize,
+    }
+
+    impl Device for OutputTrackingDevice {
+        type Error = ();
+
+        fn step(&mut self, _ctx: &StepContext) -> Result<(), Self::Error> {
+            if !self.enabled {
+                self.steps_while_disabled += 1;
+            }
+            Ok(())
+        }
+
+        fn enable_outputs(&mut self) -> Result<(), Self::Error> {
+            self.enabled = true;
+            Ok(())
+        }
+
+        fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+            self.enabled = false;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn disable_outputs_after_idle_timeout() {
+        let clock = ManualClock::with_auto_advance(Duration::from_secs(1));
+        let mut dev = OutputTrackingDevice::default();
+        let mut driver = Driver::new();
+        driver.set_max_speed(10.0);
+        driver.set_idle_timeout(Some(Duration::from_secs(5)));
+
+        driver.move_to(3);
+        while driver.is_running() {
+            driver.poll(&mut dev, &clock).unwrap();
+        }
+        assert!(dev.enabled);
+        assert!(driver.outputs_enabled());
+
+        // the clock ticks once per second, so we should time out
+        // eventually
+        for _ in 0..10 {
+            driver.poll(&mut dev, &clock).unwrap();
+        }
+        assert!(!dev.enabled);
+        assert!(!driver.outputs_enabled());
+
+        // and the outputs are re-enabled before we start moving again
+        driver.move_to(0);
+        while driver.is_running() {
+            driver.poll(&mut dev, &clock).unwrap();
+        }
+        assert_eq!(driver.current_position(), 0);
+        assert!(dev.enabled);
+        assert_eq!(dev.steps_while_disabled, 0);
+    }
+
+    #[test]
+    fn fire_steps_on_a_schedule() {
+        let mut driver = Driver::new();
+        driver.set_max_speed(100.0);
+        driver.set_acceleration(50.0);
+        driver.move_to(20);
+
+        let mut previous_deadline = Duration::new(0, 0);
+        let mut steps = 0;
+
+        while let Some(deadline) = driver.next_step_time() {
+            assert!(deadline > previous_deadline);
+            assert!(driver.fire_step(NopDevice, deadline).unwrap());
+
+            previous_deadline = deadline;
+            steps += 1;
+        }
+
+        assert_eq!(steps, 20);
+        assert_eq!(driver.current_position(), 20);
+        assert!(!driver.is_running());
+        assert!(!driver.fire_step(NopDevice, previous_deadline).unwrap());
+    }
+
+    /// Run the driver to completion, recording the position and time of
+    /// each step.
+    fn record_steps(driver: &mut Driver) -> std::vec::Vec<(i64, f64)> {
+        let mut steps = std::vec::Vec::new();
+
+        while let Some(deadline) = driver.next_step_time() {
+            driver.fire_step(NopDevice, deadline).unwrap();
+            steps.push((driver.current_position(), deadline.as_secs_f64()));
+        }
+
+        steps
+    }
+
+    fn s_curve_driver() -> Driver {
+        let mut driver = Driver::new();
+        driver.set_max_speed(1000.0);
+        driver.set_acceleration(4000.0);
+        driver.set_jerk(40_000.0);
+
+        driver
+    }
+
+    #[test]
+    fn s_curve_changes_in_step_interval_are_bounded() {
+        let mut driver = s_curve_driver();
+        driver.move_to(2000);
+
+        let steps = record_steps(&mut driver);
+        assert_eq!(driver.current_position(), 2000);
+        assert_eq!(steps.len(), 2000);
+
+        // estimate the speed, acceleration, and jerk from consecutive step
+        // intervals
+        let mut previous_speed = 0.0;
+        let mut previous_acceleration = 0.0;
+        let last = steps.len() - 1;
+
+        for i in 1..steps.len() {
+            let interval = steps[i].1 - steps[i - 1].1;
+            let speed = interval.recip();
+            let acceleration = (speed - previous_speed) / interval;
+            let jerk = (acceleration - previous_acceleration) / interval;
+
+            // ignore the very first and last steps, where the estimate using
+            // the average speed between steps is quite coarse
+            if i > 2 && i < last {
+                assert!(speed <= 1000.0 * 1.01, "step {}: {}", i, speed);
+                assert!(
+                    acceleration.abs() <= 4000.0 * 1.1,
+                    "step {}: {}",
+                    i,
+                    acceleration
+                );
+                assert!(jerk.abs() <= 40_000.0 * 1.1, "step {}: {}", i, jerk);
+            }
+
+            previous_speed = speed;
+            previous_acceleration = acceleration;
+        }
+    }
+
+    #[test]
+    fn retarget_s_curve_mid_move() {
+        let mut driver = s_curve_driver();
+        driver.move_to(1000);
+
+        while driver.current_position() < 300 {
+            let deadline = driver.next_step_time().unwrap();
+            driver.fire_step(NopDevice, deadline).unwrap();
+        }
+        driver.move_to(-100);
+
+        let steps = record_steps(&mut driver);
+        assert_eq!(driver.current_position(), -100);
+
+        // we should keep going forwards while slowing down, then turn around
+        // exactly once
+        let turning_points = steps
+            .windows(3)
+            .filter(|w| (w[1].0 - w[0].0) != (w[2].0 - w[1].0))
+            .count();
+        assert_eq!(turning_points, 1);
+        assert!(steps.iter().any(|&(position, _)| position > 300));
+    }
+
+    #[test]
+    fn stop_during_s_curve() {
+        let mut driver = s_curve_driver();
+        driver.move_to(10_000);
+
+        while driver.current_position() < 500 {
+            let deadline = driver.next_step_time().unwrap();
+            driver.fire_step(NopDevice, deadline).unwrap();
+        }
+        driver.stop();
+        let target = driver.target_position();
+        assert!(target > 500 && target < 10_000);
+
+        let steps = record_steps(&mut driver);
+
+        assert_eq!(driver.current_position(), target);
+        assert!(steps.windows(2).all(|w| w[1].0 == w[0].0 + 1));
+    }
+
+    #[test]
+    fn s_curve_step_times_match_a_bisection_search() {
+        let jerk = 40_000.0;
+        let cases = [
+            (Motion::AT_REST, 4000.0),
+            (
+                Motion {
+                    speed: 150.0,
+                    acceleration: 1000.0,
+                },
+                4000.0,
+            ),
+            (
+                Motion {
+                    speed: 800.0,
+                    acceleration: 3000.0,
+                },
+                0.0,
+            ),
+            (
+                Motion {
+                    speed: 500.0,
+                    acceleration: 0.0,
+                },
+                -4000.0,
+            ),
+            (
+                Motion {
+                    speed: 30.0,
+                    acceleration: -4000.0,
+                },
+                -4000.0,
+            ),
+        ];
+
+        for &(motion, target_acceleration) in &cases {
+            // bracket the step, then narrow it down with a slow but simple
+            // bisection
+            let travelled = |t: f32| {
+                let (distance, next) =
+                    motion.after(t, target_acceleration, jerk);
+                (distance >= 1.0, next.speed <= 0.0)
+            };
+            let mut upper = 1e-4;
+            let mut expected = None;
+            while upper < 10.0 {
+                match travelled(upper) {
+                    (true, _) => {
+                        expected = Some(upper);
+                        break;
+                    },
+                    (false, true) => break,
+                    (false, false) => upper *= 2.0,
+                }
+            }
+            if let Some(mut upper) = expected {
+                let mut lower = 0.0;
+                for _ in 0..40 {
+                    let middle = (lower + upper) / 2.0;
+                    if travelled(middle).0 {
+                        upper = middle;
+                    } else {
+                        lower = middle;
+                    }
+                }
+                expected = Some(upper);
+            }
+
+            let got = motion.time_to_next_step(target_acceleration, jerk);
+
+            match (expected, got) {
+                (Some(expected), Some((got, _))) => assert!(
+                    (got - expected).abs() <= expected * 1e-4,
+                    "{:?} -> {}: {} != {}",
+                    motion,
+                    target_acceleration,
+                    got,
+                    expected
+                ),
+                (None, None) => {},
+                (expected, got) => {
+                    panic!("{:?}: {:?} != {:?}", motion, got, expected)
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn switching_off_the_jerk_mid_move_keeps_the_current_speed() {
+        let mut driver = s_curve_driver();
+        driver.move_to(2000);
+
+        while driver.current_position() < 500 {
+            let deadline = driver.next_step_time().unwrap();
+            driver.fire_step(NopDevice, deadline).unwrap();
+        }
+        let speed = f64::from(driver.speed());
+        driver.set_jerk(0.0);
+
+        let steps = record_steps(&mut driver);
+        assert_eq!(driver.current_position(), 2000);
+
+        // the first few steps continue at roughly the same speed instead of
+        // starting again from a standstill
+        let interval = steps[1].1 - steps[0].1;
+        assert!(
+            (interval.recip() - speed).abs() < speed * 0.1,
+            "{} vs {}",
+            interval.recip(),
+            speed
+        );
+    }
+
+    #[test]
+    fn run_to_new_position_blocks_until_the_target_is_reached() {
+        let clock = ManualClock::with_auto_advance(Duration::from_secs(1));
+        let mut driver = Driver::new();
+        driver.set_max_speed(10.0);
+        driver.set_acceleration(10.0);
+
+        let outcome = driver
+            .run_to_new_position(5, NopDevice, &clock, None, || false)
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::Reached);
+        assert_eq!(driver.current_position(), 5);
+        assert!(!driver.is_running());
+    }
+
+    #[test]
+    fn blocking_moves_can_time_out() {
+        let clock = ManualClock::with_auto_advance(Duration::from_secs(1));
+        let mut driver = Driver::new();
+
+        let outcome = driver
+            .run_to_new_position(
+                100,
+                NopDevice,
+                &clock,
+                Some(Duration::from_secs(10)),
+                || false,
+            )
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::TimedOut);
+        assert!(driver.current_position() < 100);
+        assert!(driver.is_running());
+    }
+
+    #[test]
+    fn blocking_timeouts_survive_the_clock_wrapping() {
+        let clock = WrappingMillis::default();
+        let mut driver = Driver::new();
+        driver.set_max_speed(1.0);
+        driver.set_acceleration(1.0);
+
+        let outcome = driver
+            .run_to_new_position(
+                100,
+                NopDevice,
+                &clock,
+                Some(Duration::from_millis(2500)),
+                || false,
+            )
+            .unwrap();
+
+        // the clock wrapped twice, but we still time out
+        assert_eq!(outcome, RunOutcome::TimedOut);
+        assert!(clock.ticks.get() < 2600, "{}", clock.ticks.get());
+    }
+
+    #[test]
+    fn idle_timeout_survives_the_clock_wrapping() {
+        let clock = WrappingMillis::default();
+        let mut dev = OutputTrackingDevice::default();
+        let mut driver = Driver::new();
+        driver.set_max_speed(1000.0);
+        driver.set_idle_timeout(Some(Duration::from_millis(500)));
+
+        // take a step just before the clock wraps around
+        clock.ticks.set(900);
+        driver.move_to(1);
+        while driver.is_running() {
+            driver.poll(&mut dev, &clock).unwrap();
+        }
+
+        for _ in 0..700 {
+            driver.poll(&mut dev, &clock).unwrap();
+        }
+        assert!(!driver.outputs_enabled());
+    }
+
+    #[test]
+    fn blocking_moves_can_be_aborted() {
+        let clock = ManualClock::with_auto_advance(Duration::from_secs(1));
+        let mut driver = Driver::new();
+        let mut polls = 0;
+
+        let outcome = driver
+            .run_to_new_position(100, NopDevice, &clock, None, || {
+                polls += 1;
+                polls > 3
+            })
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::Aborted);
+        assert_eq!(polls, 4);
+        assert!(driver.is_running());
+    }
+
+    #[test]
+    fn run_speed_to_position_steps_at_a_constant_speed() {
+        let clock = ManualClock::with_auto_advance(Duration::from_secs(1));
+        let mut driver = Driver::new();
+        driver.set_max_speed(10.0);
+        driver.move_to(5);
+        driver.set_speed(2.0);
+
+        let outcome = driver
+            .run_speed_to_position(NopDevice, &clock, None, || false)
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::Reached);
+        assert_eq!(driver.current_position(), 5);
+        assert_eq!(driver.speed(), 2.0);
+    }
+
+    #[test]
+    fn run_speed_to_position_can_time_out_or_be_aborted() {
+        let clock = ManualClock::with_auto_advance(Duration::from_secs(1));
+        let mut driver = Driver::new();
+        driver.set_max_speed(10.0);
+        driver.move_to(100);
+        driver.set_speed(1.0);
+
+        let outcome = driver
+            .run_speed_to_position(
+                NopDevice,
+                &clock,
+                Some(Duration::from_secs(10)),
+                || false,
+            )
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::TimedOut);
+        assert!(driver.current_position() < 100);
+
+        let outcome = driver
+            .run_speed_to_position(NopDevice, &clock, None, || true)
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::Aborted);
+        assert!(driver.distance_to_go() > 0);
+    }
+
+    #[test]
+    fn retargeting_behind_us_decelerates_before_turning_around() {
+        let mut driver = Driver::new();
+        driver.set_max_speed(100.0);
+        driver.set_acceleration(200.0);
+        driver.move_to(1000);
+
+        while driver.current_position() < 50 {
+            let deadline = driver.next_step_time().unwrap();
+            driver.fire_step(NopDevice, deadline).unwrap();
+        }
+        driver.move_to(0);
+
+        let steps = record_steps(&mut driver);
+        assert_eq!(driver.current_position(), 0);
+
+        let turning_points = steps
+            .windows(3)
+            .filter(|w| (w[1].0 - w[0].0) != (w[2].0 - w[1].0))
+            .count();
+        assert_eq!(turning_points, 1);
+        assert!(steps.iter().any(|&(position, _)| position > 50));
+    }
+
+    /// Take `count` steps, recording the position and time of each.
+    fn fire_steps(
+        driver: &mut Driver,
+        count: usize,
+    ) -> std::vec::Vec<(i64, f64)> {
+        let mut steps = std::vec::Vec::new();
+
+        for _ in 0..count {
+            let deadline = driver.next_step_time().unwrap();
+            driver.fire_step(NopDevice, deadline).unwrap();
+            steps.push((driver.current_position(), deadline.as_secs_f64()));
+        }
+
+        steps
+    }
+
+    fn jogging_driver() -> Driver {
+        let mut driver = Driver::new();
+        driver.set_max_speed(500.0);
+        driver.set_acceleration(1000.0);
+
+        driver
+    }
+
+    #[test]
+    fn accelerate_to_the_target_speed_and_keep_going() {
+        let mut driver = jogging_driver();
+        driver.set_target_speed(300.0);
+
+        let steps = fire_steps(&mut driver, 1000);
+
+        // v^2 = 2as, so we should be at full speed after 45 steps
+        assert_eq!(driver.speed(), 300.0);
+        assert!(driver.is_running());
+        assert_eq!(driver.current_position(), 1000);
+        assert_eq!(driver.distance_to_go(), 0);
+
+        let intervals: std::vec::Vec<f64> =
+            steps.windows(2).map(|w| w[1].1 - w[0].1).collect();
+        assert!(intervals.windows(2).all(|w| w[1] <= w[0] + 1e-6));
+        assert!((intervals[intervals.len() - 1] - 1.0 / 300.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reverse_through_zero_in_velocity_mode() {
+        let mut driver = jogging_driver();
+        driver.set_target_speed(300.0);
+        fire_steps(&mut driver, 100);
+
+        driver.set_target_speed(-300.0);
+        let steps = fire_steps(&mut driver, 500);
+
+        assert_eq!(driver.speed(), -300.0);
+        let turning_points = steps
+            .windows(3)
+            .filter(|w| (w[1].0 - w[0].0) != (w[2].0 - w[1].0))
+            .count();
+        assert_eq!(turning_points, 1);
+
+        // and bring it to a halt
+        driver.set_target_speed(0.0);
+        let steps = record_steps(&mut driver);
+        assert!(steps.len() < 100);
+        assert!(!driver.is_running());
+    }
+
+    #[test]
+    fn switch_from_velocity_mode_to_positioning_mode() {
+        let mut driver = jogging_driver();
+        driver.set_target_speed(-400.0);
+        fire_steps(&mut driver, 200);
+        let interval = driver.step_interval;
+
+        driver.move_to(driver.current_position() - 500);
+        assert_eq!(driver.target_speed(), None);
+
+        // we shouldn't need to stop first
+        let ratio = driver.step_interval.as_secs_f32() / interval.as_secs_f32();
+        assert!(ratio > 0.9 && ratio <= 1.0, "{}", ratio);
+
+        let steps = record_steps(&mut driver);
+        assert_eq!(steps.len(), 500);
+        assert_eq!(driver.current_position(), -700);
+        assert!(steps.windows(2).all(|w| w[1].0 == w[0].0 - 1));
+
+        // stopping also switches back to positioning mode
+        driver.set_target_speed(200.0);
+        fire_steps(&mut driver, 100);
+        driver.stop();
+        let steps = record_steps(&mut driver);
+        assert_eq!(driver.target_speed(), None);
+        assert_eq!(driver.current_position(), driver.target_position());
+        assert!(steps.len() <= 22, "{}", steps.len());
+    }
+
+    #[test]
+    fn predicted_move_times_match_reality() {
+        for &(max_speed, acceleration, target) in &[
+            (100.0, 50.0, 1000),
+            (500.0, 200.0, -300),
+            (50.0, 1000.0, 200),
+        ] {
+            let mut driver = Driver::new();
+            driver.set_max_speed(max_speed);
+            driver.set_acceleration(acceleration);
+            let prediction = driver.predict_move(target);
+
+            driver.move_to(target);
+            let steps = record_steps(&mut driver);
+
+            let actual = steps.last().unwrap().1 as f32;
+            let predicted = prediction.total_time().as_secs_f32();
+            let error = (actual - predicted).abs() / predicted;
+            assert!(error < 0.05, "{} vs {}", actual, predicted);
+        }
+    }
+
+    /// Helpers for running the original C++ `AccelStepper` side-by-side with
+    /// our implementation.
+    mod original {
+        use std::{
+            os::raw::c_ulong,
+            sync::{Mutex, MutexGuard},
+            time::Duration,
+        };
+        use AccelStepper_sys::AccelStepper;
+
+        extern "C" {
+            /// The value returned by `micros()`.
+            static mut MICROS: c_ulong;
+        }
+
+        /// `AccelStepper` relies on global state, so only one test may use it
+        /// at a time.
+        static LOCK: Mutex<()> = Mutex::new(());
+
+        // Note: we can't record steps from the callbacks because they are
+        // picked based on the sign of the speed and not the direction
+        unsafe extern "C" fn step() {}
+
+        pub struct Original {
+            pub stepper: AccelStepper,
+            _guard: MutexGuard<'static, ()>,
+        }
+
+        impl Original {
+            pub fn new() -> Original {
+                let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+                unsafe {
+                    MICROS = 0;
+
+                    Original {
+                        stepper: AccelStepper::new1(Some(step), Some(step)),
+                        _guard: guard,
+                    }
+                }
+            }
+
+            pub fn set_time(&mut self, time: Duration) {
+                unsafe {
+                    MICROS = time.as_micros() as c_ulong;
+                }
+            }
+
+            // c_long is only 32 bits on some platforms
+            #[allow(clippy::unnecessary_cast)]
+            pub fn current_position(&mut self) -> i64 {
+                unsafe { self.stepper.currentPosition() as i64 }
+            }
+        }
+    }
+
+    /// Run both implementations for a while, calling `poll` on ours and
+    /// `run` on the original every 100us, and make sure they step at the same
+    /// times.
+    fn compare_with_original<P, R>(
+        driver: &mut Driver,
+        original: &mut original::Original,
+        duration: Duration,
+        mut poll: P,
+        mut run: R,
+    ) -> std::vec::Vec<(i64, Duration)>
+    where
+        P: FnMut(&mut Driver, &mut Recorder, &ManualClock),
+        R: FnMut(&mut AccelStepper_sys::AccelStepper),
+    {
+        let clock = ManualClock::new();
+        let tick = Duration::from_micros(100);
+        let mut device = Recorder::default();
+        let mut original_steps = std::vec::Vec::new();
+
+        while clock.now() < duration {
+            let now = clock.now() + tick;
+            clock.set(now);
+            original.set_time(now);
+
+            poll(driver, &mut device, &clock);
+
+            let previous_position = original.current_position();
+            run(&mut original.stepper);
+            if original.current_position() != previous_position {
+                original_steps.push((original.current_position(), now));
+            }
+        }
+
+        assert_eq!(device.steps, original_steps);
+
+        device.steps
+    }
+
+    #[test]
+    fn poll_speed_to_position_matches_the_original() {
+        for &(target, speed) in &[(25, 40.0), (-10, 50.0), (7, -20.0)] {
+            let mut driver = Driver::new();
+            driver.set_max_speed(100.0);
+            driver.move_to(target);
+            driver.set_speed(speed);
+
+            let mut original = original::Original::new();
+            unsafe {
+                original.stepper.setMaxSpeed(100.0);
+                original.stepper.moveTo(target);
+                original.stepper.setSpeed(speed);
+            }
+
+            let steps = compare_with_original(
+                &mut driver,
+                &mut original,
+                Duration::from_secs(2),
+                |driver, dev, clock| {
+                    driver.poll_speed_to_position(dev, clock).unwrap();
+                },
+                |stepper| unsafe {
+                    stepper.runSpeedToPosition();
+                },
+            );
+
+            // we should stop exactly at the target, even though there was
+            // plenty of time to keep going
+            assert_eq!(driver.current_position(), target);
+            assert_eq!(steps.len(), target.unsigned_abs() as usize);
+        }
+    }
+
+    #[test]
+    fn poll_at_constant_speed_matches_the_original() {
+        let mut driver = Driver::new();
+        driver.set_max_speed(100.0);
+        driver.set_speed(-50.0);
+
+        let mut original = original::Original::new();
+        unsafe {
+            original.stepper.setMaxSpeed(100.0);
+            original.stepper.setSpeed(-50.0);
+        }
+
+        compare_with_original(
+            &mut driver,
+            &mut original,
+            Duration::from_secs(1),
+            |driver, dev, clock| {
+                driver.poll_at_constant_speed(dev, clock).unwrap();
+            },
+            |stepper| unsafe {
+                stepper.runSpeed();
+            },
+        );
+
+        // the target is ignored, we just keep moving at a constant speed
+        assert_eq!(driver.current_position(), -50);
+    }
 }
@@ -0,0 +1,1405 @@
+//! A G-code front-end for driving a [`MultiDriver`].
+//!
+//! Lines are parsed and executed one at a time by an [`Interpreter`], with
+//! straight-line moves being added to the [`MultiDriver`]'s queue so speed is
+//! carried through corners. The following commands are supported:
+//!
+//! | Command       | Description                                               |
+//! | ------------- | --------------------------------------------------------- |
+//! | `G0`          | Rapid move, as fast as each axis allows                   |
+//! | `G1`          | Linear move at the feed rate (`F`, in units/minute)       |
+//! | `G2`/`G3`     | Clockwise/counter-clockwise arc (`I`/`J`/`K` or `R`)      |
+//! | `G4`          | Dwell for `P` seconds                                     |
+//! | `G17`-`G19`   | Select the plane for arcs                                 |
+//! | `G20`/`G21`   | Use inches/millimetres                                    |
+//! | `G28`         | Return to the origin, optionally via an intermediate point |
+//! | `G90`/`G91`   | Absolute/relative positioning                             |
+//! | `G92`         | Set the current position                                  |
+//! | `M17`/`M18`   | Enable/disable the motor outputs                          |
+//!
+//! Axes are named `X`, `Y`, `Z`, `A`, `B` and `C`, in the same order as the
+//! machine's Cartesian axes (see [`crate::Kinematics`]). Comments (`;` to the
+//! end of the line, or inside parentheses), line numbers (`N`), and
+//! spindle/tool words (`S` and `T`) are ignored. An arc with `I`/`J`/`K` words
+//! but no axis words is a full circle back to where it started.
+//!
+//! # Examples
+#![cfg_attr(feature = "manual-clock", doc = "```rust")]
+#![cfg_attr(not(feature = "manual-clock"), doc = "```rust,ignore")]
+//! use accel_stepper::{
+//!     gcode::Interpreter, CummulativeSteps, Driver, ManualClock, MultiDriver,
+//! };
+//! # use accel_stepper::{Device, StepContext};
+//! # use core::time::Duration;
+//! # struct Nop;
+//! # impl Device for Nop {
+//! #     type Error = ();
+//! #     fn step(&mut self, _: &StepContext) -> Result<(), ()> { Ok(()) }
+//! # }
+//! # let mut devices = [Nop, Nop];
+//! let clock = ManualClock::with_auto_advance(Duration::from_micros(50));
+//!
+//! let mut machine = MultiDriver::new();
+//! for _ in 0..2 {
+//!     let mut driver = Driver::new();
+//!     driver.set_max_speed(2000.0);
+//!     driver.set_acceleration(5000.0);
+//!     machine.push_driver(driver);
+//! }
+//!
+//! // both axes use 80 steps/mm
+//! let axes = [CummulativeSteps::new(80.0), CummulativeSteps::new(80.0)];
+//! let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+//!
+//! let program = "G21 G90\nG1 X10 F600 ; move 10mm\nG0 Y5";
+//!
+//! for line in program.lines() {
+//!     // keep trying until there's room to execute the line
+//!     while let Err(e) = interpreter.execute(line, &mut machine) {
+//!         assert!(e.is_busy(), "{:?}", e);
+//!         interpreter.poll(&mut machine, &mut devices, &clock)?;
+//!     }
+//! }
+//!
+//! while interpreter.is_running(&machine) {
+//!     interpreter.poll(&mut machine, &mut devices, &clock)?;
+//! }
+//!
+//! let positions: Vec<_> =
+//!     machine.drivers().iter().map(|d| d.current_position()).collect();
+//! assert_eq!(positions, [800, 400]);
+//! # Ok::<(), ()>(())
+//! ```
+
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use libm::F32Ext;
+
+use crate::{
+    arc::Chords,
+    multi_driver::{euclidean_distance, Axes},
+    utils::Stopwatch,
+    ArcCentre, ArcDirection, ArcError, CummulativeSteps, Device, Kinematics,
+    MoveError, MultiDriver, Plane, SystemClock, Unreachable,
+};
+use arrayvec::ArrayVec;
+use core::{str::Chars, time::Duration};
+
+/// The letters used for each axis, in order.
+const AXIS_LETTERS: [char; 6] = ['X', 'Y', 'Z', 'A', 'B', 'C'];
+const MM_PER_INCH: f32 = 25.4;
+
+/// A single letter/number pair from a line of G-code (e.g. `X12.5`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Word {
+    letter: char,
+    value: f32,
+}
+
+impl Word {
+    /// The (uppercase) letter.
+    pub fn letter(&self) -> char { self.letter }
+
+    /// The number following the letter.
+    pub fn value(&self) -> f32 { self.value }
+
+    fn is(&self, letter: char, value: f32) -> bool {
+        self.letter == letter && self.value == value
+    }
+}
+
+/// The words from a single line of G-code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    words: ArrayVec<Word, { Block::MAX_WORDS }>,
+}
+
+impl Block {
+    /// The maximum number of words allowed on a single line.
+    pub const MAX_WORDS: usize = 16;
+
+    /// Parse a line of G-code.
+    pub fn parse(line: &str) -> Result<Block, ErrorKind> {
+        let mut words = ArrayVec::new();
+        let mut chars = line.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => break,
+                '(' => {
+                    if !chars.any(|c| c == ')') {
+                        return Err(ErrorKind::UnterminatedComment);
+                    }
+                },
+                c if c.is_whitespace() => {},
+                c if c.is_ascii_alphabetic() => {
+                    let letter = c.to_ascii_uppercase();
+                    let value = parse_number(&mut chars)
+                        .ok_or(ErrorKind::InvalidNumber(letter))?;
+
+                    words
+                        .try_push(Word { letter, value })
+                        .map_err(|_| ErrorKind::TooManyWords)?;
+                },
+                other => return Err(ErrorKind::UnexpectedCharacter(other)),
+            }
+        }
+
+        Ok(Block { words })
+    }
+
+    /// Every word in the block, in the order they were written.
+    pub fn words(&self) -> &[Word] { &self.words }
+
+    /// Get the value for a particular letter, if present.
+    pub fn get(&self, letter: char) -> Option<f32> {
+        self.words
+            .iter()
+            .find(|w| w.letter == letter)
+            .map(|w| w.value)
+    }
+
+    fn contains(&self, letter: char, value: f32) -> bool {
+        self.words.iter().any(|w| w.is(letter, value))
+    }
+}
+
+/// Parse the number immediately after a letter, stopping at the first
+/// character that can't be part of it.
+fn parse_number(chars: &mut Chars<'_>) -> Option<f32> {
+    let text = chars.as_str();
+    let text = text.trim_start();
+    let length = text
+        .char_indices()
+        .find(|&(i, c)| {
+            !(c.is_ascii_digit()
+                || c == '.'
+                || (i == 0 && (c == '-' || c == '+')))
+        })
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+
+    let (number, rest) = text.split_at(length);
+    *chars = rest.chars();
+
+    number.parse().ok()
+}
+
+/// Something that went wrong while executing a line of G-code.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Error {
+    line: usize,
+    kind: ErrorKind,
+}
+
+impl Error {
+    /// The (1-based) number of the line which caused the error.
+    pub fn line(&self) -> usize { self.line }
+
+    /// What went wrong.
+    pub fn kind(&self) -> ErrorKind { self.kind }
+
+    /// Is this error just saying the line can't be executed yet? If so, the
+    /// same line should be executed again after polling the [`Interpreter`]
+    /// for a while.
+    pub fn is_busy(&self) -> bool { self.kind == ErrorKind::Busy }
+}
+
+/// The different reasons a line of G-code couldn't be executed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// The line contained a character which isn't part of a word or comment.
+    UnexpectedCharacter(char),
+    /// The letter wasn't followed by a valid number.
+    InvalidNumber(char),
+    /// A `(` comment was never closed.
+    UnterminatedComment,
+    /// There were more than [`Block::MAX_WORDS`] words on the line.
+    TooManyWords,
+    /// This `G` or `M` command isn't supported.
+    UnsupportedCommand(char, f32),
+    /// The letter isn't used by any supported command.
+    UnsupportedWord(char),
+    /// The line refers to an axis which isn't being controlled.
+    UnknownAxis(char),
+    /// A command needs a parameter which wasn't provided (e.g. `P` for
+    /// `G4`).
+    MissingParameter(char),
+    /// A `G1`, `G2` or `G3` move was requested before setting a feed rate.
+    MissingFeedRate,
+    /// The feed rate (`F`) must be a positive number.
+    InvalidFeedRate,
+    /// An arc was requested without `I`/`J`/`K` or `R` words.
+    MissingArcCentre,
+    /// The arc couldn't be drawn.
+    Arc(ArcError),
+    /// The move would leave the machine's workspace (see
+    /// [`crate::Kinematics`]).
+    Unreachable,
+    /// The line can't be executed until previous commands have finished.
+    Busy,
+}
+
+/// The currently active motion mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Motion {
+    Rapid,
+    Linear,
+    Arc(ArcDirection),
+}
+
+/// Something which needs to happen once the machine stops moving.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Pending {
+    Dwell {
+        duration: Duration,
+        stopwatch: Stopwatch,
+    },
+    EnableOutputs,
+    DisableOutputs,
+}
+
+/// An arc which is being added to the queue one chord at a time.
+///
+/// The chords are worked out in millimetres and each vertex is converted to
+/// steps separately, so the arc stays circular even when the plane's axes
+/// have a different number of steps per millimetre.
+#[derive(Debug, Clone, PartialEq)]
+struct ArcChords {
+    /// The chords within the plane, in millimetres.
+    chords: Chords,
+    /// The indices of the plane's two axes.
+    plane: (usize, usize),
+    /// Where the arc starts, in millimetres from the origin.
+    start: Axes<f32>,
+    /// Where the arc ends, in millimetres from the origin.
+    end: Axes<f32>,
+    /// Where the arc starts, in steps along each Cartesian axis.
+    start_steps: Axes<f32>,
+    /// Where the arc ends, in steps along each Cartesian axis.
+    end_steps: Axes<f32>,
+    steps_per_mm: Axes<f32>,
+    /// The index of the next chord to queue, starting from `1`.
+    next_chord: u32,
+    /// How fast to travel along the arc, in millimetres per second.
+    feed_rate: f32,
+}
+
+impl ArcChords {
+    /// Where the chord with this index finishes, in millimetres. Axes
+    /// outside the plane move linearly, turning the arc into a helix.
+    fn vertex(&self, index: u32) -> Axes<f32> {
+        let fraction = index as f32 / self.chords.count() as f32;
+        let on_arc = self.chords.vertex(index);
+
+        self.start
+            .iter()
+            .zip(self.end.iter())
+            .enumerate()
+            .map(|(i, (start, end))| {
+                if i == self.plane.0 {
+                    on_arc.0
+                } else if i == self.plane.1 {
+                    on_arc.1
+                } else {
+                    start + (end - start) * fraction
+                }
+            })
+            .collect()
+    }
+
+    /// Where the chord with this index finishes, in steps along each
+    /// Cartesian axis.
+    fn vertex_steps(&self, index: u32) -> Axes<f32> {
+        if index == self.chords.count() {
+            return self.end_steps.clone();
+        }
+
+        self.vertex(index)
+            .iter()
+            .zip(self.start.iter())
+            .zip(self.start_steps.iter().zip(self.steps_per_mm.iter()))
+            .map(|((mm, start), (start_steps, steps_per_mm))| {
+                start_steps + (mm - start) * steps_per_mm
+            })
+            .collect()
+    }
+
+    /// Add as many chords to the queue as will fit, returning `true` once
+    /// the entire arc has been queued.
+    fn queue<K: Kinematics, const N: usize>(
+        &mut self,
+        machine: &mut MultiDriver<K, N>,
+    ) -> bool {
+        while self.next_chord <= self.chords.count() {
+            if machine.free_slots() == 0 {
+                // don't bother working out the next chord yet
+                return false;
+            }
+
+            let index = self.next_chord;
+            let target = self.vertex_steps(index);
+
+            // convert the feed rate to steps/second along this chord
+            let mm = euclidean_distance(
+                &self.vertex(index - 1),
+                &self.vertex(index),
+            );
+            let steps =
+                euclidean_distance(&self.vertex_steps(index - 1), &target);
+            if mm > 0.0 && steps > 0.0 {
+                machine.set_max_path_speed(Some(self.feed_rate * steps / mm));
+            }
+
+            match machine.enqueue_cartesian(&target) {
+                Err(MoveError::QueueFull) => return false,
+                // Every vertex is reachable, so the chord must clip the edge
+                // of the workspace. Skip it and cut straight across to the
+                // next vertex instead.
+                Ok(()) | Err(MoveError::Unreachable) => self.next_chord += 1,
+            }
+        }
+
+        true
+    }
+}
+
+/// A move which is added to the queue as room becomes available.
+#[derive(Debug, Clone, PartialEq)]
+enum Deferred {
+    Arc(ArcChords),
+    /// A rapid move to this position, in steps along each Cartesian axis.
+    Rapid(Axes<f32>),
+}
+
+impl Deferred {
+    /// Add as much of the move to the queue as will fit, returning `true`
+    /// once all of it has been queued.
+    fn queue<K: Kinematics, const N: usize>(
+        &mut self,
+        machine: &mut MultiDriver<K, N>,
+    ) -> bool {
+        match self {
+            Deferred::Arc(arc) => arc.queue(machine),
+            Deferred::Rapid(target) => {
+                machine.set_max_path_speed(None);
+                // we already made sure the line is reachable
+                machine.enqueue_cartesian(target) != Err(MoveError::QueueFull)
+            },
+        }
+    }
+}
+
+/// The interpreter's modal state.
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    axes: Axes<CummulativeSteps>,
+    /// Where each axis will be once every command has been executed, in
+    /// millimetres from the origin.
+    position: Axes<f32>,
+    /// The offset applied to each axis by `G92`, in millimetres.
+    offsets: Axes<f32>,
+    /// The step position corresponding to `position`.
+    steps: Axes<i64>,
+    inches: bool,
+    relative: bool,
+    plane: Plane,
+    motion: Motion,
+    /// The feed rate, in millimetres per minute.
+    feed_rate: Option<f32>,
+}
+
+/// Executes G-code by sending commands to a [`MultiDriver`].
+///
+/// Positions in the G-code are Cartesian coordinates, so moves are added to
+/// the queue with [`MultiDriver::enqueue_cartesian()`] and the machine's
+/// [`Kinematics`] decide how each motor moves. Arcs are split into chords
+/// no further than [`MultiDriver::arc_tolerance()`] from the true arc, which
+/// are queued like any other straight line.
+///
+/// Arcs are worked out in millimetres before being converted to steps, so
+/// they stay circular (and keep to the feed rate) when the two axes in the
+/// plane have a different number of steps per millimetre. In that case the
+/// tolerance applies to whichever axis has more steps per millimetre.
+///
+/// The [`Interpreter`] assumes it has exclusive control of the
+/// [`MultiDriver`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpreter {
+    state: State,
+    pending: Option<Pending>,
+    /// A move which hasn't been completely added to the queue yet.
+    deferred: Option<Deferred>,
+    lines_executed: usize,
+}
+
+impl Interpreter {
+    /// Create a new [`Interpreter`] starting from the [`MultiDriver`]'s
+    /// current position, where `axes` converts from millimetres to steps
+    /// along each of the machine's Cartesian axes.
+    ///
+    /// The [`MultiDriver`] should be stationary, and an error is returned if
+    /// its [`Kinematics`] can't work out where it is.
+    ///
+    /// # Panics
+    ///
+    /// At most 6 axes are supported, and there must be one for each of the
+    /// machine's Cartesian axes.
+    pub fn new<K: Kinematics, const N: usize>(
+        axes: &[CummulativeSteps],
+        machine: &MultiDriver<K, N>,
+    ) -> Result<Interpreter, Unreachable> {
+        assert!(axes.len() <= AXIS_LETTERS.len());
+        assert_eq!(axes.len(), cartesian_axes(machine));
+
+        let mut current: Axes<f32> = axes.iter().map(|_| 0.0).collect();
+        machine.cartesian_position(&mut current)?;
+
+        let mut axes: Axes<CummulativeSteps> = axes
+            .iter()
+            .map(|a| CummulativeSteps::new(a.steps_per_unit()))
+            .collect();
+        let position: Axes<f32> = axes
+            .iter()
+            .zip(current.iter())
+            .map(|(axis, steps)| steps / axis.steps_per_unit())
+            .collect();
+        let steps: Axes<i64> = axes
+            .iter_mut()
+            .zip(position.iter())
+            .map(|(axis, &mm)| axis.move_by(mm))
+            .collect();
+
+        Ok(Interpreter {
+            state: State {
+                axes,
+                offsets: position.iter().map(|_| 0.0).collect(),
+                position,
+                steps,
+                inches: false,
+                relative: false,
+                plane: Plane::XY,
+                motion: Motion::Rapid,
+                feed_rate: None,
+            },
+            pending: None,
+            deferred: None,
+            lines_executed: 0,
+        })
+    }
+
+    /// The number of lines executed so far.
+    pub fn lines_executed(&self) -> usize { self.lines_executed }
+
+    /// Where each axis will be (in the current units and coordinate system)
+    /// once every command executed so far is complete.
+    pub fn position(&self, axis: usize) -> f32 {
+        let state = &self.state;
+        let position = state.position[axis] - state.offsets[axis];
+
+        if state.inches {
+            position / MM_PER_INCH
+        } else {
+            position
+        }
+    }
+
+    /// Is the machine still moving, or are there commands waiting to be
+    /// executed?
+    pub fn is_running<K: Kinematics, const N: usize>(
+        &self,
+        machine: &MultiDriver<K, N>,
+    ) -> bool {
+        self.pending.is_some()
+            || self.deferred.is_some()
+            || machine.is_running()
+    }
+
+    /// Execute a single line of G-code.
+    ///
+    /// If the line can't be executed yet (e.g. because the queue is full),
+    /// an error with [`ErrorKind::Busy`] is returned and nothing is changed,
+    /// so the line should be retried after polling the [`Interpreter`].
+    ///
+    /// # Panics
+    ///
+    /// The [`MultiDriver`] must have the same number of Cartesian axes as the
+    /// [`Interpreter`].
+    pub fn execute<K: Kinematics, const N: usize>(
+        &mut self,
+        line: &str,
+        machine: &mut MultiDriver<K, N>,
+    ) -> Result<(), Error> {
+        assert_eq!(cartesian_axes(machine), self.state.axes.len());
+
+        let result = if self.pending.is_some() || self.deferred.is_some() {
+            Err(ErrorKind::Busy)
+        } else {
+            Block::parse(line)
+                .and_then(|block| self.execute_block(&block, machine))
+        };
+
+        match result {
+            Err(ErrorKind::Busy) => Err(Error {
+                line: self.lines_executed + 1,
+                kind: ErrorKind::Busy,
+            }),
+            other => {
+                self.lines_executed += 1;
+                other.map_err(|kind| Error {
+                    line: self.lines_executed,
+                    kind,
+                })
+            },
+        }
+    }
+
+    /// Poll the [`MultiDriver`], and carry out any commands that were
+    /// waiting for it to stop moving.
+    pub fn poll<K, D, C, const N: usize>(
+        &mut self,
+        machine: &mut MultiDriver<K, N>,
+        devices: &mut [D],
+        clock: &C,
+    ) -> Result<(), D::Error>
+    where
+        K: Kinematics,
+        D: Device,
+        C: SystemClock,
+    {
+        machine.poll(devices, clock)?;
+
+        if let Some(ref mut deferred) = self.deferred {
+            if deferred.queue(machine) {
+                self.deferred = None;
+            }
+        }
+
+        if self.deferred.is_some() || machine.is_running() {
+            return Ok(());
+        }
+
+        match self.pending {
+            Some(Pending::Dwell {
+                duration,
+                ref mut stopwatch,
+            }) => {
+                let waited = stopwatch.elapsed(clock.elapsed());
+
+                if waited >= duration {
+                    self.pending = None;
+                }
+            },
+            Some(Pending::EnableOutputs) => {
+                for (driver, device) in
+                    machine.drivers_mut().iter_mut().zip(devices.iter_mut())
+                {
+                    driver.enable_outputs(device)?;
+                }
+                self.pending = None;
+            },
+            Some(Pending::DisableOutputs) => {
+                for (driver, device) in
+                    machine.drivers_mut().iter_mut().zip(devices.iter_mut())
+                {
+                    driver.disable_outputs(device)?;
+                }
+                self.pending = None;
+            },
+            None => {},
+        }
+
+        Ok(())
+    }
+
+    fn execute_block<K: Kinematics, const N: usize>(
+        &mut self,
+        block: &Block,
+        machine: &mut MultiDriver<K, N>,
+    ) -> Result<(), ErrorKind> {
+        if let Some(feed_rate) = block.get('F') {
+            if feed_rate <= 0.0 || !feed_rate.is_finite() {
+                return Err(ErrorKind::InvalidFeedRate);
+            }
+        }
+
+        // work on a copy so a failed line doesn't leave us half-updated
+        let mut state = self.state.clone();
+        let mut pending = None;
+        let mut deferred = None;
+        let mut axis_words_used = false;
+
+        for word in block.words() {
+            match word.letter {
+                'G' | 'M' => check_supported(word)?,
+                'F' | 'P' | 'R' | 'I' | 'J' | 'K' | 'N' | 'S' | 'T' => {},
+                letter => {
+                    match AXIS_LETTERS.iter().position(|&l| l == letter) {
+                        Some(i) if i < state.axes.len() => {},
+                        Some(_) => return Err(ErrorKind::UnknownAxis(letter)),
+                        None => return Err(ErrorKind::UnsupportedWord(letter)),
+                    }
+                },
+            }
+        }
+
+        // modal settings come first, so they apply to the rest of the line
+        if block.contains('G', 20.0) {
+            state.inches = true;
+        }
+        if block.contains('G', 21.0) {
+            state.inches = false;
+        }
+        if block.contains('G', 90.0) {
+            state.relative = false;
+        }
+        if block.contains('G', 91.0) {
+            state.relative = true;
+        }
+        if block.contains('G', 17.0) {
+            state.plane = Plane::XY;
+        }
+        if block.contains('G', 18.0) {
+            state.plane = Plane::ZX;
+        }
+        if block.contains('G', 19.0) {
+            state.plane = Plane::YZ;
+        }
+        if let Some(feed_rate) = block.get('F') {
+            state.feed_rate = Some(state.to_mm(feed_rate));
+        }
+        if block.contains('G', 0.0) {
+            state.motion = Motion::Rapid;
+        }
+        if block.contains('G', 1.0) {
+            state.motion = Motion::Linear;
+        }
+        if block.contains('G', 2.0) {
+            state.motion = Motion::Arc(ArcDirection::Clockwise);
+        }
+        if block.contains('G', 3.0) {
+            state.motion = Motion::Arc(ArcDirection::CounterClockwise);
+        }
+
+        if block.contains('G', 4.0) {
+            let seconds =
+                block.get('P').ok_or(ErrorKind::MissingParameter('P'))?;
+            pending = Some(Pending::Dwell {
+                duration: Duration::from_millis((seconds * 1000.0) as u64),
+                stopwatch: Stopwatch::new(),
+            });
+        }
+        if block.contains('M', 17.0) {
+            pending = Some(Pending::EnableOutputs);
+        }
+        if block.contains('M', 18.0) {
+            pending = Some(Pending::DisableOutputs);
+        }
+
+        if block.contains('G', 92.0) {
+            axis_words_used = true;
+
+            for (i, &letter) in
+                AXIS_LETTERS.iter().enumerate().take(state.axes.len())
+            {
+                if let Some(value) = block.get(letter) {
+                    state.offsets[i] = state.position[i] - state.to_mm(value);
+                }
+            }
+        } else if block.contains('G', 28.0) {
+            axis_words_used = true;
+            deferred = Some(state.go_home(block, machine)?);
+        }
+
+        let has_axis_words = AXIS_LETTERS
+            .iter()
+            .take(state.axes.len())
+            .any(|&l| block.get(l).is_some());
+
+        // an arc's centre is enough to make it move, so an arc without any
+        // axis words is a full circle back to where it started
+        let has_arc_words = match state.motion {
+            Motion::Arc(_) => {
+                ['I', 'J', 'K', 'R'].iter().any(|&l| block.get(l).is_some())
+            },
+            _ => false,
+        };
+
+        if (has_axis_words || has_arc_words) && !axis_words_used {
+            let target = state.target(block);
+
+            match state.motion {
+                Motion::Rapid => state.move_to(&target, None, machine)?,
+                Motion::Linear => {
+                    let feed_rate =
+                        state.feed_rate.ok_or(ErrorKind::MissingFeedRate)?;
+                    state.move_to(&target, Some(feed_rate), machine)?;
+                },
+                Motion::Arc(direction) => {
+                    let feed_rate =
+                        state.feed_rate.ok_or(ErrorKind::MissingFeedRate)?;
+                    deferred = Some(Deferred::Arc(state.arc_to(
+                        &target, block, direction, feed_rate, machine,
+                    )?));
+                },
+            }
+        }
+
+        // queue as much of the move as we can, and the rest will be queued
+        // while polling
+        if let Some(ref mut remaining) = deferred {
+            if remaining.queue(machine) {
+                deferred = None;
+            }
+        }
+
+        self.state = state;
+        self.pending = pending;
+        self.deferred = deferred;
+
+        Ok(())
+    }
+}
+
+/// The number of Cartesian axes on a machine.
+fn cartesian_axes<K: Kinematics, const N: usize>(
+    machine: &MultiDriver<K, N>,
+) -> usize {
+    machine.kinematics().cartesian_axes(machine.drivers().len())
+}
+
+/// Add a straight line to the [`MultiDriver`]'s queue, travelling at `speed`
+/// `steps/second`. The path speed is left alone if the line can't be queued.
+fn enqueue_line<K: Kinematics, const N: usize>(
+    machine: &mut MultiDriver<K, N>,
+    target: &[f32],
+    speed: Option<f32>,
+) -> Result<(), ErrorKind> {
+    let previous_speed = machine.max_path_speed();
+    machine.set_max_path_speed(speed);
+
+    machine.enqueue_cartesian(target).map_err(|e| {
+        machine.set_max_path_speed(previous_speed);
+
+        match e {
+            MoveError::QueueFull => ErrorKind::Busy,
+            MoveError::Unreachable => ErrorKind::Unreachable,
+        }
+    })
+}
+
+/// Make sure we know how to handle a `G` or `M` word.
+fn check_supported(word: &Word) -> Result<(), ErrorKind> {
+    const G_CODES: &[f32] = &[
+        0.0, 1.0, 2.0, 3.0, 4.0, 17.0, 18.0, 19.0, 20.0, 21.0, 28.0, 90.0,
+        91.0, 92.0,
+    ];
+    const M_CODES: &[f32] = &[17.0, 18.0];
+
+    let supported = if word.letter == 'G' { G_CODES } else { M_CODES };
+
+    if supported.contains(&word.value) {
+        Ok(())
+    } else {
+        Err(ErrorKind::UnsupportedCommand(word.letter, word.value))
+    }
+}
+
+impl State {
+    /// Convert a value in the current units to millimetres.
+    fn to_mm(&self, value: f32) -> f32 {
+        if self.inches {
+            value * MM_PER_INCH
+        } else {
+            value
+        }
+    }
+
+    /// Where the line's axis words say each axis should go, in millimetres
+    /// from the origin.
+    fn target(&self, block: &Block) -> Axes<f32> {
+        (0..self.axes.len())
+            .map(|i| match block.get(AXIS_LETTERS[i]) {
+                Some(value) if self.relative => {
+                    self.position[i] + self.to_mm(value)
+                },
+                Some(value) => self.to_mm(value) + self.offsets[i],
+                None => self.position[i],
+            })
+            .collect()
+    }
+
+    /// The current position, in steps along each Cartesian axis.
+    fn cartesian_steps(&self) -> Axes<f32> {
+        self.steps.iter().map(|&s| s as f32).collect()
+    }
+
+    /// Update the current position, returning the distance travelled in
+    /// millimetres and steps.
+    fn advance_to(&mut self, target: &[f32]) -> (f32, f32) {
+        let mut mm = 0.0;
+        let mut steps = 0.0;
+
+        for (i, &target) in target.iter().enumerate() {
+            let delta = target - self.position[i];
+            let delta_steps = self.axes[i].move_by(delta);
+
+            self.position[i] = target;
+            self.steps[i] += delta_steps;
+            mm += delta * delta;
+            steps += (delta_steps * delta_steps) as f32;
+        }
+
+        (mm.sqrt(), steps.sqrt())
+    }
+
+    /// Queue up a straight-line move.
+    fn move_to<K: Kinematics, const N: usize>(
+        &mut self,
+        target: &[f32],
+        feed_rate: Option<f32>,
+        machine: &mut MultiDriver<K, N>,
+    ) -> Result<(), ErrorKind> {
+        let (mm, steps) = self.advance_to(target);
+
+        if steps > 0.0 {
+            // convert from mm/min to steps/second along the path
+            let speed = feed_rate.map(|f| f / 60.0 * steps / mm);
+            enqueue_line(machine, &self.cartesian_steps(), speed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Move to the origin, via an intermediate point if any axes were
+    /// specified.
+    ///
+    /// The move to the intermediate point is queued straight away, and the
+    /// move home is returned so it can be queued once there is room. Both
+    /// legs are checked before anything is queued.
+    fn go_home<K: Kinematics, const N: usize>(
+        &mut self,
+        block: &Block,
+        machine: &mut MultiDriver<K, N>,
+    ) -> Result<Deferred, ErrorKind> {
+        let axes_specified: Axes<bool> = (0..self.axes.len())
+            .map(|i| block.get(AXIS_LETTERS[i]).is_some())
+            .collect();
+        let all_axes = !axes_specified.iter().any(|&b| b);
+
+        let intermediate = self.target(block);
+        let home: Axes<f32> = (0..self.axes.len())
+            .map(|i| {
+                if all_axes || axes_specified[i] {
+                    0.0
+                } else {
+                    intermediate[i]
+                }
+            })
+            .collect();
+
+        let (_, steps) = self.advance_to(&intermediate);
+        let via = self.cartesian_steps();
+        self.advance_to(&home);
+        let home = self.cartesian_steps();
+
+        machine
+            .check_cartesian_line(&via, &home)
+            .map_err(|_| ErrorKind::Unreachable)?;
+
+        if steps > 0.0 {
+            enqueue_line(machine, &via, None)?;
+        }
+
+        Ok(Deferred::Rapid(home))
+    }
+
+    /// Work out the chords for an arc, making sure the machine can reach the
+    /// end of each one.
+    fn arc_to<K: Kinematics, const N: usize>(
+        &mut self,
+        target: &[f32],
+        block: &Block,
+        direction: ArcDirection,
+        feed_rate: f32,
+        machine: &MultiDriver<K, N>,
+    ) -> Result<ArcChords, ErrorKind> {
+        let (first, second) = self.plane.axes();
+
+        if first >= self.axes.len() || second >= self.axes.len() {
+            return Err(ErrorKind::UnknownAxis(
+                AXIS_LETTERS[first.max(second)],
+            ));
+        }
+
+        let offset_letters = ['I', 'J', 'K'];
+
+        let centre = match block.get('R') {
+            Some(radius) => ArcCentre::Radius(self.to_mm(radius)),
+            None => {
+                let i = block.get(offset_letters[first]);
+                let j = block.get(offset_letters[second]);
+
+                if i.is_none() && j.is_none() {
+                    return Err(ErrorKind::MissingArcCentre);
+                }
+
+                ArcCentre::Offset(
+                    self.to_mm(i.unwrap_or(0.0)),
+                    self.to_mm(j.unwrap_or(0.0)),
+                )
+            },
+        };
+
+        let steps_per_mm: Axes<f32> =
+            self.axes.iter().map(|a| a.steps_per_unit()).collect();
+        // the tolerance is in steps, so use the finer of the two axes to
+        // keep both of them within it
+        let tolerance = machine.arc_tolerance()
+            / steps_per_mm[first].max(steps_per_mm[second]);
+
+        let start = self.position.clone();
+        let start_steps = self.cartesian_steps();
+        self.advance_to(target);
+
+        let chords = Chords::new(
+            (start[first], start[second]),
+            (self.position[first], self.position[second]),
+            centre,
+            direction,
+            tolerance,
+        )
+        .map_err(ErrorKind::Arc)?;
+        let arc = ArcChords {
+            chords,
+            plane: (first, second),
+            start,
+            end: self.position.clone(),
+            start_steps,
+            end_steps: self.cartesian_steps(),
+            steps_per_mm,
+            next_chord: 1,
+            feed_rate: feed_rate / 60.0,
+        };
+
+        let mut motors: Axes<f32> =
+            machine.drivers().iter().map(|_| 0.0).collect();
+
+        for index in 1..=arc.chords.count() {
+            machine
+                .kinematics()
+                .to_motors(&arc.vertex_steps(index), &mut motors)
+                .map_err(|_| ErrorKind::Unreachable)?;
+        }
+
+        Ok(arc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{ticking_clock, Recorder},
+        Cartesian, CoreXY, Driver, LinearDelta, ManualClock,
+    };
+    use std::vec::Vec;
+
+    fn machine(axes: usize) -> MultiDriver { machine_with(Cartesian, axes) }
+
+    fn machine_with<K: Kinematics>(
+        kinematics: K,
+        axes: usize,
+    ) -> MultiDriver<K> {
+        let mut machine = MultiDriver::with_kinematics(kinematics);
+
+        for _ in 0..axes {
+            let mut driver = Driver::new();
+            driver.set_max_speed(4000.0);
+            driver.set_acceleration(20_000.0);
+            machine.push_driver(driver);
+        }
+
+        machine
+    }
+
+    /// Execute a program, returning the final position of each axis.
+    fn run(program: &str, axes: &[CummulativeSteps]) -> Vec<i64> {
+        run_on(&mut machine(axes.len()), program, axes)
+    }
+
+    /// Execute a program on a particular machine, returning the final
+    /// position of each motor.
+    fn run_on<K: Kinematics>(
+        machine: &mut MultiDriver<K>,
+        program: &str,
+        axes: &[CummulativeSteps],
+    ) -> Vec<i64> {
+        let mut interpreter = Interpreter::new(axes, machine).unwrap();
+        let mut devices: Vec<_> = machine
+            .drivers()
+            .iter()
+            .map(|_| Recorder::default())
+            .collect();
+        let clock = ticking_clock();
+
+        for line in program.lines() {
+            loop {
+                match interpreter.execute(line, machine) {
+                    Ok(()) => break,
+                    Err(e) if e.is_busy() => {
+                        interpreter.poll(machine, &mut devices, &clock).unwrap()
+                    },
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        }
+
+        while interpreter.is_running(machine) {
+            interpreter.poll(machine, &mut devices, &clock).unwrap();
+        }
+
+        machine
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect()
+    }
+
+    #[test]
+    fn parse_a_line() {
+        let block =
+            Block::parse("N10 g1 X-1.5 (comment) Y+2 F300 ; more").unwrap();
+
+        let letters: Vec<_> =
+            block.words().iter().map(|w| w.letter()).collect();
+        assert_eq!(letters, ['N', 'G', 'X', 'Y', 'F']);
+        assert_eq!(block.get('X'), Some(-1.5));
+        assert_eq!(block.get('Y'), Some(2.0));
+        assert_eq!(block.get('Z'), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Block::parse("G1 X"), Err(ErrorKind::InvalidNumber('X')));
+        assert_eq!(
+            Block::parse("G1 (oops"),
+            Err(ErrorKind::UnterminatedComment)
+        );
+        assert_eq!(
+            Block::parse("G1 X1 #"),
+            Err(ErrorKind::UnexpectedCharacter('#'))
+        );
+    }
+
+    #[test]
+    fn absolute_and_relative_moves() {
+        let axes = [CummulativeSteps::new(80.0), CummulativeSteps::new(40.0)];
+        let program = "G21 G90 G1 F1200 X10 Y10\nG91 X-2.5\nG0 Y1\nG90 G20 X1";
+
+        // the final X1 is 1 inch
+        assert_eq!(run(program, &axes), [2032, 440]);
+    }
+
+    #[test]
+    fn set_position_and_go_home() {
+        let axes = [CummulativeSteps::new(10.0), CummulativeSteps::new(10.0)];
+
+        // G92 makes the current position (10, 10) become (0, 0)
+        let program = "G0 X10 Y10\nG92 X0 Y0\nG0 X5 Y-5";
+        assert_eq!(run(program, &axes), [150, 50]);
+
+        // G28 returns to the machine's origin, ignoring the G92 offset
+        let program = "G0 X10 Y10\nG92 X0 Y0\nG28";
+        assert_eq!(run(program, &axes), [0, 0]);
+
+        // only the Y axis is homed, via Y=2
+        let program = "G0 X10 Y10\nG28 Y2";
+        assert_eq!(run(program, &axes), [100, 0]);
+    }
+
+    #[test]
+    fn go_home_when_each_leg_needs_several_queue_slots() {
+        let axes = [
+            CummulativeSteps::new(10.0),
+            CummulativeSteps::new(10.0),
+            CummulativeSteps::new(10.0),
+        ];
+        // short segments, so every leg is split into more pieces than the
+        // queue can hold
+        let mut delta = LinearDelta::new(2500.0, 1000.0);
+        delta.set_segment_length(5.0);
+        let height = (2500.0_f32 * 2500.0 - 1000.0 * 1000.0).sqrt();
+        let mut machine = machine_with(delta, 3);
+        for driver in machine.drivers_mut() {
+            driver.set_current_position(height.round() as i64);
+        }
+        run_on(&mut machine, "G0 X20 Y10", &axes);
+
+        let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+        let mut devices = [
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        ];
+        let clock = ticking_clock();
+
+        // the first leg is queued and the rest follows while polling
+        interpreter.execute("G91 G28 X10", &mut machine).unwrap();
+        assert!(interpreter
+            .execute("G90", &mut machine)
+            .unwrap_err()
+            .is_busy());
+
+        let mut position = [0.0; 3];
+        let mut furthest = 0.0_f32;
+        while interpreter.is_running(&machine) {
+            interpreter
+                .poll(&mut machine, &mut devices, &clock)
+                .unwrap();
+            machine.cartesian_position(&mut position).unwrap();
+            furthest = furthest.max(position[0]);
+        }
+
+        // X went via X=30 on the way back to the origin, and Y stayed put
+        assert!((furthest - 300.0).abs() < 2.0, "{}", furthest);
+        assert!(position[0].abs() < 1.0, "{:?}", position);
+        assert!((position[1] - 100.0).abs() < 1.0, "{:?}", position);
+        assert_eq!(interpreter.position(0), 0.0);
+        assert!((interpreter.position(1) - 10.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn arcs_and_dwells() {
+        let axes = [CummulativeSteps::new(10.0), CummulativeSteps::new(10.0)];
+        let program = "G1 F600 X10\nG4 P0.5\nG3 X0 Y10 I-10 J0\nG2 X10 Y0 R10";
+
+        assert_eq!(run(program, &axes), [100, 0]);
+    }
+
+    #[test]
+    fn arcs_stay_circular_with_different_steps_per_mm() {
+        let axes = [CummulativeSteps::new(80.0), CummulativeSteps::new(100.0)];
+        let mut machine = machine(2);
+        machine.drivers_mut()[0].set_current_position(800);
+        let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+        let mut devices = [Recorder::default(), Recorder::default()];
+        let clock = ticking_clock();
+
+        interpreter
+            .execute("G3 X0 Y10 I-10 J0 F600", &mut machine)
+            .unwrap();
+
+        while interpreter.is_running(&machine) {
+            interpreter
+                .poll(&mut machine, &mut devices, &clock)
+                .unwrap();
+
+            let x = machine.drivers()[0].current_position() as f32 / 80.0;
+            let y = machine.drivers()[1].current_position() as f32 / 100.0;
+            let radius = x.hypot(y);
+            assert!((radius - 10.0).abs() < 0.05, "({}, {})", x, y);
+        }
+
+        assert_eq!(machine.drivers()[0].current_position(), 0);
+        assert_eq!(machine.drivers()[1].current_position(), 1000);
+        // a quarter of a 10mm circle at 10mm/s, plus a little to speed up
+        // and slow down
+        let finished = devices[1].times().last().unwrap().as_secs_f32();
+        assert!(finished > 1.57 && finished < 1.65, "{}", finished);
+    }
+
+    #[test]
+    fn arcs_without_axis_words_are_full_circles() {
+        let axes = [CummulativeSteps::new(10.0), CummulativeSteps::new(10.0)];
+        let circle = |program: &str| {
+            let mut machine = machine(2);
+            let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+            let mut devices = [Recorder::default(), Recorder::default()];
+            let clock = ticking_clock();
+
+            for line in program.lines() {
+                while let Err(e) = interpreter.execute(line, &mut machine) {
+                    assert!(e.is_busy(), "{:?}", e);
+                    interpreter
+                        .poll(&mut machine, &mut devices, &clock)
+                        .unwrap();
+                }
+            }
+            while interpreter.is_running(&machine) {
+                interpreter
+                    .poll(&mut machine, &mut devices, &clock)
+                    .unwrap();
+            }
+
+            assert_eq!(machine.drivers()[0].current_position(), 100);
+            assert_eq!(machine.drivers()[1].current_position(), 0);
+            [devices[0].steps.len(), devices[1].steps.len()]
+        };
+
+        let implicit = circle("G1 F600 X10\nG2 I-10 J0");
+        let explicit = circle("G1 F600 X10\nG2 X10 Y0 I-10 J0");
+        assert_eq!(implicit, explicit);
+        // out to X=10, then about 4 * 2r along each axis
+        assert!(implicit[0] > 450 && implicit[1] > 350, "{:?}", implicit);
+
+        // a radius on its own can't describe a full circle
+        let mut machine = machine(2);
+        let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+        let err = interpreter.execute("G2 R5 F600", &mut machine).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Arc(ArcError::RadiusTooSmall));
+    }
+
+    #[test]
+    fn dwells_survive_the_clock_going_backwards() {
+        let axes = [CummulativeSteps::new(10.0)];
+        let mut machine = machine(1);
+        let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+        let mut devices = [Recorder::default()];
+        let clock = ManualClock::new();
+        clock.set(Duration::from_secs(10));
+
+        interpreter.execute("G4 P0.5", &mut machine).unwrap();
+        interpreter
+            .poll(&mut machine, &mut devices, &clock)
+            .unwrap();
+        clock.advance(Duration::from_millis(300));
+        interpreter
+            .poll(&mut machine, &mut devices, &clock)
+            .unwrap();
+
+        // the clock wraps around, but we've still waited 300ms so far
+        clock.set(Duration::from_millis(0));
+        interpreter
+            .poll(&mut machine, &mut devices, &clock)
+            .unwrap();
+        assert!(interpreter.is_running(&machine));
+
+        clock.set(Duration::from_millis(250));
+        interpreter
+            .poll(&mut machine, &mut devices, &clock)
+            .unwrap();
+        assert!(!interpreter.is_running(&machine));
+    }
+
+    #[test]
+    fn start_from_the_machine_position() {
+        let axes = [CummulativeSteps::new(10.0), CummulativeSteps::new(10.0)];
+        let mut machine = machine(2);
+        machine.drivers_mut()[0].set_current_position(50);
+        machine.drivers_mut()[1].set_current_position(-20);
+
+        let interpreter = Interpreter::new(&axes, &machine).unwrap();
+        assert_eq!(interpreter.position(0), 5.0);
+        assert_eq!(interpreter.position(1), -2.0);
+
+        assert_eq!(run_on(&mut machine, "G91 G0 X1 Y1", &axes), [60, -10]);
+    }
+
+    #[test]
+    fn moves_use_the_machine_kinematics() {
+        let axes = [CummulativeSteps::new(10.0), CummulativeSteps::new(10.0)];
+        let mut machine = machine_with(CoreXY, 2);
+
+        // motor A moves by X + Y and motor B by X - Y
+        assert_eq!(run_on(&mut machine, "G0 X10 Y5", &axes), [150, 50]);
+
+        // arcs are traced out in Cartesian coordinates too
+        let program = "G1 F600 X10 Y0\nG3 X0 Y10 I-10 J0";
+        assert_eq!(run_on(&mut machine, program, &axes), [100, -100]);
+    }
+
+    #[test]
+    fn motor_outputs() {
+        let axes = [CummulativeSteps::new(10.0)];
+        let mut machine = machine(1);
+        let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+        let mut devices = [Recorder::default()];
+        let clock = ticking_clock();
+
+        interpreter.execute("G0 X1 M18", &mut machine).unwrap();
+        // we need to wait until the move is done
+        assert!(interpreter
+            .execute("M17", &mut machine)
+            .unwrap_err()
+            .is_busy());
+
+        while interpreter.is_running(&machine) {
+            interpreter
+                .poll(&mut machine, &mut devices, &clock)
+                .unwrap();
+        }
+
+        assert_eq!(devices[0].steps.len(), 10);
+        assert_eq!(devices[0].enabled, Some(false));
+        assert!(!machine.drivers()[0].outputs_enabled());
+
+        interpreter.execute("M17", &mut machine).unwrap();
+        interpreter
+            .poll(&mut machine, &mut devices, &clock)
+            .unwrap();
+        assert_eq!(devices[0].enabled, Some(true));
+    }
+
+    #[test]
+    fn errors_are_reported_per_line() {
+        let axes = [CummulativeSteps::new(10.0), CummulativeSteps::new(10.0)];
+        let mut machine = machine(2);
+        let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+
+        interpreter.execute("G21", &mut machine).unwrap();
+
+        let err = interpreter.execute("G1 X10", &mut machine).unwrap_err();
+        assert_eq!(err.line(), 2);
+        assert_eq!(err.kind(), ErrorKind::MissingFeedRate);
+
+        let err = interpreter.execute("G0 Z5", &mut machine).unwrap_err();
+        assert_eq!(err.line(), 3);
+        assert_eq!(err.kind(), ErrorKind::UnknownAxis('Z'));
+
+        let err = interpreter.execute("G38.2 X1", &mut machine).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnsupportedCommand('G', 38.2));
+
+        let err = interpreter.execute("G2 X1 Y1", &mut machine).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingFeedRate);
+
+        let err = interpreter
+            .execute("G2 X1 Y1 F100", &mut machine)
+            .unwrap_err();
+        assert_eq!(err.line(), 6);
+        assert_eq!(err.kind(), ErrorKind::MissingArcCentre);
+
+        let err = interpreter
+            .execute("G2 X10 Y0 R2 F100", &mut machine)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Arc(ArcError::RadiusTooSmall));
+
+        // failed lines don't change anything
+        assert_eq!(interpreter.position(0), 0.0);
+        assert_eq!(machine.max_path_speed(), None);
+        assert!(!machine.is_running());
+        assert_eq!(interpreter.lines_executed(), 7);
+    }
+
+    #[test]
+    fn feed_rates_must_be_positive() {
+        let axes = [CummulativeSteps::new(10.0), CummulativeSteps::new(10.0)];
+        let mut machine = machine(2);
+        let mut interpreter = Interpreter::new(&axes, &machine).unwrap();
+
+        for line in &["G1 F0 X1", "G91 G1 F-100 X1", "G2 X1 Y1 R1 F0"] {
+            let err = interpreter.execute(line, &mut machine).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidFeedRate, "{}", line);
+        }
+
+        // the bad feed rate was rejected before anything else on the line
+        assert_eq!(
+            interpreter
+                .execute("G1 X1", &mut machine)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::MissingFeedRate
+        );
+        assert_eq!(run_on(&mut machine, "G1 F600 X1", &axes), [10, 0]);
+    }
+}
@@ -0,0 +1,75 @@
+//! [`Device`] implementations built on top of [`embedded-hal` 1.0][eh1].
+//!
+//! These mirror the devices available with the `hal` feature (which targets
+//! `embedded-hal` 0.2), but use the 1.0 [`OutputPin`] and [`DelayNs`] traits.
+//!
+//! Requires the `embedded-hal-1` feature.
+//!
+//! [eh1]: https://crates.io/crates/embedded-hal
+
+use crate::{Device, LimitSwitch, StepContext};
+use core::time::Duration;
+use embedded_hal_1::{
+    delay::DelayNs,
+    digital::{ErrorType, InputPin, OutputPin},
+};
+
+pub use crate::hal_common::Inverted;
+
+crate::hal_common::hal_devices!(feature = "embedded-hal-1", delay = DelayNs);
+
+impl<P: ErrorType> ErrorType for Inverted<P> {
+    type Error = P::Error;
+}
+
+impl<P: OutputPin> OutputPin for Inverted<P> {
+    #[inline]
+    fn set_low(&mut self) -> Result<(), Self::Error> { self.0.set_high() }
+
+    #[inline]
+    fn set_high(&mut self) -> Result<(), Self::Error> { self.0.set_low() }
+}
+
+impl<P: InputPin> InputPin for Inverted<P> {
+    #[inline]
+    fn is_high(&mut self) -> Result<bool, Self::Error> { self.0.is_low() }
+
+    #[inline]
+    fn is_low(&mut self) -> Result<bool, Self::Error> { self.0.is_high() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh1::{
+        delay::{CheckedDelay, NoopDelay, Transaction as DelayTransaction},
+        digital::{Mock, State, Transaction},
+    };
+
+    crate::hal_common::hal_device_tests!();
+
+    #[test]
+    fn step_pulse_respects_timing() {
+        let step = expect_states(&[1, 0, 1, 0]);
+        let direction = expect_states(&[0]);
+        let delay = CheckedDelay::new(&[
+            DelayTransaction::delay_us(2),
+            DelayTransaction::delay_us(5),
+            DelayTransaction::delay_us(3),
+            DelayTransaction::delay_us(5),
+            DelayTransaction::delay_us(3),
+        ]);
+        let mut dev =
+            StepAndDirection::new(step.clone(), direction.clone(), delay);
+        dev.set_direction_setup_time(Duration::from_micros(2));
+        dev.set_min_pulse_width(Duration::from_micros(5));
+        dev.set_direction_hold_time(Duration::from_micros(3));
+
+        step_through(&mut dev, &[-1, -2]);
+
+        let (mut step, mut direction, mut delay) = dev.into_inner();
+        step.done();
+        direction.done();
+        delay.done();
+    }
+}
@@ -0,0 +1,929 @@
+//! Functionality shared by the `embedded-hal` 0.2 and 1.0 devices.
+
+use core::time::Duration;
+
+/// An output pin adapter which inverts the pin's polarity, equivalent to
+/// AccelStepper's `setPinsInverted()`.
+///
+/// Wrap any pin given to one of the HAL devices to make it active-low (e.g.
+/// for an inverting buffer or an active-low enable input). Inversion is
+/// resolved at compile time, so pins which aren't wrapped pay nothing for it.
+///
+/// Requires the `hal` or `embedded-hal-1` feature.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Inverted<P>(pub P);
+
+impl<P> Inverted<P> {
+    pub fn new(pin: P) -> Self { Inverted(pin) }
+
+    pub fn into_inner(self) -> P { self.0 }
+}
+
+pub(crate) fn as_micros_ceil(duration: Duration) -> u32 {
+    let micros = duration.as_nanos().div_ceil(1000);
+
+    if micros > u128::from(u32::MAX) {
+        u32::MAX
+    } else {
+        micros as u32
+    }
+}
+
+// The coil sequences below are output masks where bit 0 corresponds to the
+// first pin.
+
+pub(crate) fn full_2_wire(position: i64) -> u8 {
+    // copied straight from AccelStepper::step2()
+    match position & 0x03 {
+        0 => 0b10,
+        1 => 0b11,
+        2 => 0b01,
+        3 => 0b00,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn full_3_wire(position: i64) -> u8 {
+    // copied from AccelStepper::step3(), using a euclidean remainder so
+    // negative positions continue the sequence
+    match position.rem_euclid(3) {
+        0 => 0b100,
+        1 => 0b001,
+        2 => 0b010,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn half_3_wire(position: i64) -> u8 {
+    // copied from AccelStepper::step6()
+    match position.rem_euclid(6) {
+        0 => 0b100,
+        1 => 0b101,
+        2 => 0b001,
+        3 => 0b011,
+        4 => 0b010,
+        5 => 0b110,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn full_4_wire(position: i64) -> u8 {
+    // copied straight from AccelStepper::step4()
+    match position & 0x03 {
+        0 => 0b0101,
+        1 => 0b0110,
+        2 => 0b1010,
+        3 => 0b1001,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn half_4_wire(position: i64) -> u8 {
+    // copied straight from AccelStepper::step8()
+    match position & 0x07 {
+        0 => 0b0001,
+        1 => 0b0101,
+        2 => 0b0100,
+        3 => 0b0110,
+        4 => 0b0010,
+        5 => 0b1010,
+        6 => 0b1000,
+        7 => 0b1001,
+        _ => unreachable!(),
+    }
+}
+
+/// Generate the HAL devices for a particular version of `embedded-hal`.
+///
+/// Both backends share this one implementation. The calling module must have
+/// that version's `OutputPin` and `InputPin` traits in scope (along with
+/// [`crate::Device`], [`crate::LimitSwitch`] and [`crate::StepContext`]),
+/// and `$delay` must be a trait with a `delay_us(u32)` method.
+macro_rules! hal_devices {
+    (feature = $feature:literal, delay = $delay:path $(,)?) => {
+        /// A [`Device`] for stepper drivers (e.g. the A4988 or DRV8825) which
+        /// are controlled using step and direction pins, equivalent to
+        /// AccelStepper's `DRIVER` interface.
+        ///
+        /// Each step sets the direction pin based on which way the motor is
+        /// moving, then emits a single pulse on the step pin. The `Delay` is
+        /// used to respect the driver chip's timing requirements. Use
+        /// [`Full2Wire`] if the motor's two coils are wired up directly
+        /// instead.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct StepAndDirection<Step, Direction, Delay> {
+            step: Step,
+            direction: Direction,
+            delay: Delay,
+            min_pulse_width: u32,
+            direction_setup_time: u32,
+            direction_hold_time: u32,
+            previous_direction: Option<bool>,
+        }
+
+        impl<Step, Direction, Delay> StepAndDirection<Step, Direction, Delay> {
+            pub fn new(step: Step, direction: Direction, delay: Delay) -> Self {
+                StepAndDirection {
+                    step,
+                    direction,
+                    delay,
+                    min_pulse_width: 1,
+                    direction_setup_time: 1,
+                    direction_hold_time: 0,
+                    previous_direction: None,
+                }
+            }
+
+            /// Set the minimum amount of time the step pin will be held high
+            /// for.
+            ///
+            /// Times are rounded up to the nearest microsecond. The default is
+            /// `1us`.
+            pub fn set_min_pulse_width(&mut self, width: Duration) {
+                self.min_pulse_width =
+                    $crate::hal_common::as_micros_ceil(width);
+            }
+
+            /// Get the minimum step pulse width.
+            pub fn min_pulse_width(&self) -> Duration {
+                Duration::from_micros(self.min_pulse_width.into())
+            }
+
+            /// Set how long to wait between changing the direction pin and
+            /// raising the step pin.
+            ///
+            /// Times are rounded up to the nearest microsecond. The default is
+            /// `1us`.
+            pub fn set_direction_setup_time(&mut self, setup_time: Duration) {
+                self.direction_setup_time =
+                    $crate::hal_common::as_micros_ceil(setup_time);
+            }
+
+            /// Get the direction setup time.
+            pub fn direction_setup_time(&self) -> Duration {
+                Duration::from_micros(self.direction_setup_time.into())
+            }
+
+            /// Set how long the direction pin must remain stable after the step
+            /// pin is dropped.
+            ///
+            /// Times are rounded up to the nearest microsecond. The default is
+            /// `0us`.
+            pub fn set_direction_hold_time(&mut self, hold_time: Duration) {
+                self.direction_hold_time =
+                    $crate::hal_common::as_micros_ceil(hold_time);
+            }
+
+            /// Get the direction hold time.
+            pub fn direction_hold_time(&self) -> Duration {
+                Duration::from_micros(self.direction_hold_time.into())
+            }
+
+            pub fn into_inner(self) -> (Step, Direction, Delay) {
+                (self.step, self.direction, self.delay)
+            }
+        }
+
+        fn set_output<P: OutputPin>(
+            pin: &mut P,
+            mask: u8,
+        ) -> Result<(), P::Error> {
+            if mask != 0 {
+                pin.set_high()
+            } else {
+                pin.set_low()
+            }
+        }
+
+        impl<Step, Direction, Delay, E> Device
+            for StepAndDirection<Step, Direction, Delay>
+        where
+            Step: OutputPin<Error = E>,
+            Direction: OutputPin<Error = E>,
+            Delay: $delay,
+        {
+            type Error = E;
+
+            #[inline]
+            fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+                let forward = ctx.forward;
+
+                // Set direction first else we get rogue pulses
+                if self.previous_direction != Some(forward) {
+                    set_output(&mut self.direction, forward as u8)?;
+                    self.previous_direction = Some(forward);
+                    self.delay.delay_us(self.direction_setup_time);
+                }
+
+                self.step.set_high()?;
+                self.delay.delay_us(self.min_pulse_width);
+                self.step.set_low()?;
+
+                if self.direction_hold_time > 0 {
+                    self.delay.delay_us(self.direction_hold_time);
+                }
+
+                Ok(())
+            }
+
+            fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+                self.step.set_low()?;
+                self.direction.set_low()?;
+                self.previous_direction = None;
+
+                Ok(())
+            }
+        }
+
+        /// A [`Device`] which drives the four coil pins of a unipolar or
+        /// bipolar motor directly (e.g. through a ULN2003 or L298N), one full
+        /// step at a time.
+        ///
+        /// This is equivalent to AccelStepper's `FULL4WIRE` interface.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct Full4Wire<Pin1, Pin2, Pin3, Pin4> {
+            pins: (Pin1, Pin2, Pin3, Pin4),
+        }
+
+        impl<Pin1, Pin2, Pin3, Pin4> Full4Wire<Pin1, Pin2, Pin3, Pin4> {
+            pub fn new(pin1: Pin1, pin2: Pin2, pin3: Pin3, pin4: Pin4) -> Self {
+                Full4Wire {
+                    pins: (pin1, pin2, pin3, pin4),
+                }
+            }
+
+            pub fn into_inner(self) -> (Pin1, Pin2, Pin3, Pin4) { self.pins }
+        }
+
+        impl<Pin1, Pin2, Pin3, Pin4, E> Device
+            for Full4Wire<Pin1, Pin2, Pin3, Pin4>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+            Pin3: OutputPin<Error = E>,
+            Pin4: OutputPin<Error = E>,
+        {
+            type Error = E;
+
+            #[inline]
+            fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+                set_output4(
+                    &mut self.pins,
+                    $crate::hal_common::full_4_wire(ctx.position),
+                )
+            }
+
+            fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+                set_output4(&mut self.pins, 0)
+            }
+        }
+
+        /// A [`Device`] which drives the four coil pins of a unipolar or
+        /// bipolar motor directly, taking half steps.
+        ///
+        /// This is equivalent to AccelStepper's `HALF4WIRE` interface. Keep in
+        /// mind that a motor's "steps per revolution" doubles when
+        /// half-stepping.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct Half4Wire<Pin1, Pin2, Pin3, Pin4> {
+            pins: (Pin1, Pin2, Pin3, Pin4),
+        }
+
+        impl<Pin1, Pin2, Pin3, Pin4> Half4Wire<Pin1, Pin2, Pin3, Pin4> {
+            pub fn new(pin1: Pin1, pin2: Pin2, pin3: Pin3, pin4: Pin4) -> Self {
+                Half4Wire {
+                    pins: (pin1, pin2, pin3, pin4),
+                }
+            }
+
+            pub fn into_inner(self) -> (Pin1, Pin2, Pin3, Pin4) { self.pins }
+        }
+
+        impl<Pin1, Pin2, Pin3, Pin4, E> Device
+            for Half4Wire<Pin1, Pin2, Pin3, Pin4>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+            Pin3: OutputPin<Error = E>,
+            Pin4: OutputPin<Error = E>,
+        {
+            type Error = E;
+
+            #[inline]
+            fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+                set_output4(
+                    &mut self.pins,
+                    $crate::hal_common::half_4_wire(ctx.position),
+                )
+            }
+
+            fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+                set_output4(&mut self.pins, 0)
+            }
+        }
+
+        /// A [`Device`] which drives the two coil pins of a bipolar motor,
+        /// where each coil's second terminal is driven through an inverter.
+        ///
+        /// This is equivalent to AccelStepper's `FULL2WIRE` interface. Use
+        /// [`StepAndDirection`] for driver chips with step and direction
+        /// inputs.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct Full2Wire<Pin1, Pin2> {
+            pins: (Pin1, Pin2),
+        }
+
+        impl<Pin1, Pin2> Full2Wire<Pin1, Pin2> {
+            pub fn new(pin1: Pin1, pin2: Pin2) -> Self {
+                Full2Wire { pins: (pin1, pin2) }
+            }
+
+            pub fn into_inner(self) -> (Pin1, Pin2) { self.pins }
+        }
+
+        impl<Pin1, Pin2, E> Device for Full2Wire<Pin1, Pin2>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+        {
+            type Error = E;
+
+            #[inline]
+            fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+                set_output2(
+                    &mut self.pins,
+                    $crate::hal_common::full_2_wire(ctx.position),
+                )
+            }
+
+            fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+                set_output2(&mut self.pins, 0)
+            }
+        }
+
+        /// A [`Device`] which drives the three phases of a three-wire motor
+        /// (e.g. a salvaged HDD spindle motor), one full step at a time.
+        ///
+        /// This is equivalent to AccelStepper's `FULL3WIRE` interface.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct Full3Wire<Pin1, Pin2, Pin3> {
+            pins: (Pin1, Pin2, Pin3),
+        }
+
+        impl<Pin1, Pin2, Pin3> Full3Wire<Pin1, Pin2, Pin3> {
+            pub fn new(pin1: Pin1, pin2: Pin2, pin3: Pin3) -> Self {
+                Full3Wire {
+                    pins: (pin1, pin2, pin3),
+                }
+            }
+
+            pub fn into_inner(self) -> (Pin1, Pin2, Pin3) { self.pins }
+        }
+
+        impl<Pin1, Pin2, Pin3, E> Device for Full3Wire<Pin1, Pin2, Pin3>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+            Pin3: OutputPin<Error = E>,
+        {
+            type Error = E;
+
+            #[inline]
+            fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+                set_output3(
+                    &mut self.pins,
+                    $crate::hal_common::full_3_wire(ctx.position),
+                )
+            }
+
+            fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+                set_output3(&mut self.pins, 0)
+            }
+        }
+
+        /// A [`Device`] which drives the three phases of a three-wire motor,
+        /// taking half steps.
+        ///
+        /// This is equivalent to AccelStepper's `HALF3WIRE` interface.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct Half3Wire<Pin1, Pin2, Pin3> {
+            pins: (Pin1, Pin2, Pin3),
+        }
+
+        impl<Pin1, Pin2, Pin3> Half3Wire<Pin1, Pin2, Pin3> {
+            pub fn new(pin1: Pin1, pin2: Pin2, pin3: Pin3) -> Self {
+                Half3Wire {
+                    pins: (pin1, pin2, pin3),
+                }
+            }
+
+            pub fn into_inner(self) -> (Pin1, Pin2, Pin3) { self.pins }
+        }
+
+        impl<Pin1, Pin2, Pin3, E> Device for Half3Wire<Pin1, Pin2, Pin3>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+            Pin3: OutputPin<Error = E>,
+        {
+            type Error = E;
+
+            #[inline]
+            fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+                set_output3(
+                    &mut self.pins,
+                    $crate::hal_common::half_3_wire(ctx.position),
+                )
+            }
+
+            fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+                set_output3(&mut self.pins, 0)
+            }
+        }
+
+        /// A wrapper which adds an enable pin to another [`Device`], equivalent
+        /// to AccelStepper's `setEnablePin()`.
+        ///
+        /// The enable pin is driven high when the outputs are enabled and low
+        /// when they are disabled. Wrap it in [`Inverted`] if the driver's
+        /// enable input is active-low.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct WithEnablePin<D, Enable> {
+            device: D,
+            enable: Enable,
+        }
+
+        impl<D, Enable> WithEnablePin<D, Enable> {
+            pub fn new(device: D, enable: Enable) -> Self {
+                WithEnablePin { device, enable }
+            }
+
+            pub fn device(&self) -> &D { &self.device }
+
+            pub fn device_mut(&mut self) -> &mut D { &mut self.device }
+
+            pub fn into_inner(self) -> (D, Enable) {
+                (self.device, self.enable)
+            }
+        }
+
+        impl<D, Enable> Device for WithEnablePin<D, Enable>
+        where
+            D: Device,
+            Enable: OutputPin<Error = D::Error>,
+        {
+            type Error = D::Error;
+
+            #[inline]
+            fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+                self.device.step(ctx)
+            }
+
+            fn enable_outputs(&mut self) -> Result<(), Self::Error> {
+                self.device.enable_outputs()?;
+                self.enable.set_high()
+            }
+
+            fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+                self.device.disable_outputs()?;
+                self.enable.set_low()
+            }
+        }
+
+        /// A [`LimitSwitch`] which is triggered when an [`InputPin`] is high.
+        ///
+        /// Wrap the pin in [`Inverted`] if the switch is active-low.
+        ///
+        #[doc = concat!("Requires the `", $feature, "` feature.")]
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct LimitSwitchPin<P>(P);
+
+        impl<P> LimitSwitchPin<P> {
+            pub fn new(pin: P) -> Self { LimitSwitchPin(pin) }
+
+            pub fn into_inner(self) -> P { self.0 }
+        }
+
+        impl<P: InputPin> LimitSwitch for LimitSwitchPin<P> {
+            type Error = P::Error;
+
+            #[inline]
+            fn is_triggered(&mut self) -> Result<bool, Self::Error> {
+                self.0.is_high()
+            }
+        }
+
+        /// Set two pins at once, where bit 0 of the mask corresponds to the
+        /// first pin.
+        fn set_output2<Pin1, Pin2, E>(
+            pins: &mut (Pin1, Pin2),
+            mask: u8,
+        ) -> Result<(), E>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+        {
+            set_output(&mut pins.0, mask & 0b01)?;
+            set_output(&mut pins.1, mask & 0b10)?;
+
+            Ok(())
+        }
+
+        /// Set three pins at once, where bit 0 of the mask corresponds to the
+        /// first pin.
+        fn set_output3<Pin1, Pin2, Pin3, E>(
+            pins: &mut (Pin1, Pin2, Pin3),
+            mask: u8,
+        ) -> Result<(), E>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+            Pin3: OutputPin<Error = E>,
+        {
+            set_output(&mut pins.0, mask & 0b001)?;
+            set_output(&mut pins.1, mask & 0b010)?;
+            set_output(&mut pins.2, mask & 0b100)?;
+
+            Ok(())
+        }
+
+        /// Set four pins at once, where bit 0 of the mask corresponds to the
+        /// first pin.
+        fn set_output4<Pin1, Pin2, Pin3, Pin4, E>(
+            pins: &mut (Pin1, Pin2, Pin3, Pin4),
+            mask: u8,
+        ) -> Result<(), E>
+        where
+            Pin1: OutputPin<Error = E>,
+            Pin2: OutputPin<Error = E>,
+            Pin3: OutputPin<Error = E>,
+            Pin4: OutputPin<Error = E>,
+        {
+            set_output(&mut pins.0, mask & 0b0001)?;
+            set_output(&mut pins.1, mask & 0b0010)?;
+            set_output(&mut pins.2, mask & 0b0100)?;
+            set_output(&mut pins.3, mask & 0b1000)?;
+
+            Ok(())
+        }
+    };
+}
+
+pub(crate) use hal_devices;
+
+/// Generate the tests shared by both HAL backends.
+///
+/// This expands to test functions, and expects the backend's devices plus the
+/// matching `embedded-hal-mock` `Mock`, `State`, `Transaction` and
+/// `NoopDelay` types to be in scope.
+#[cfg(test)]
+macro_rules! hal_device_tests {
+    () => {
+        #[test]
+        fn set_direction_then_pulse_step_pin() {
+            let step = Mock::new(&[
+                Transaction::set(State::High),
+                Transaction::set(State::Low),
+                Transaction::set(State::High),
+                Transaction::set(State::Low),
+                Transaction::set(State::High),
+                Transaction::set(State::Low),
+            ]);
+            // the direction pin is only touched when the direction changes
+            let direction = Mock::new(&[
+                Transaction::set(State::High),
+                Transaction::set(State::Low),
+            ]);
+            let mut dev = StepAndDirection::new(
+                step.clone(),
+                direction.clone(),
+                NoopDelay,
+            );
+
+            step_through(&mut dev, &[1, 2, 1]);
+
+            let (mut step, mut direction, _) = dev.into_inner();
+            step.done();
+            direction.done();
+        }
+
+        fn expect_states(states: &[u8]) -> Mock {
+            let transactions: std::vec::Vec<_> = states
+                .iter()
+                .map(|&s| {
+                    Transaction::set(if s != 0 {
+                        State::High
+                    } else {
+                        State::Low
+                    })
+                })
+                .collect();
+
+            Mock::new(&transactions)
+        }
+
+        fn step_through<D: Device>(dev: &mut D, positions: &[i64]) {
+            let mut previous = 0;
+
+            for &position in positions {
+                let ctx = StepContext {
+                    position,
+                    forward: position > previous,
+                    step_time: Duration::new(0, 0),
+                };
+                assert!(dev.step(&ctx).is_ok());
+                previous = position;
+            }
+        }
+
+        #[test]
+        fn full_2_wire_gray_code() {
+            let pins =
+                [expect_states(&[0, 1, 1, 0]), expect_states(&[1, 1, 0, 0])];
+            let mut dev = Full2Wire::new(pins[0].clone(), pins[1].clone());
+
+            step_through(&mut dev, &[0, 1, 2, 3]);
+
+            for mut pin in std::vec::Vec::from(pins) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn full_4_wire_sequence() {
+            let pins = [
+                expect_states(&[1, 0, 0, 1, 1]),
+                expect_states(&[0, 1, 1, 0, 0]),
+                expect_states(&[1, 1, 0, 0, 1]),
+                expect_states(&[0, 0, 1, 1, 0]),
+            ];
+            let mut dev = Full4Wire::new(
+                pins[0].clone(),
+                pins[1].clone(),
+                pins[2].clone(),
+                pins[3].clone(),
+            );
+
+            step_through(&mut dev, &[0, 1, 2, 3, 4]);
+
+            for mut pin in std::vec::Vec::from(pins) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn half_4_wire_sequence_going_backwards() {
+            let pins = [
+                expect_states(&[1, 1, 1, 0]),
+                expect_states(&[0, 0, 0, 0]),
+                expect_states(&[0, 0, 1, 1]),
+                expect_states(&[1, 0, 0, 0]),
+            ];
+            let mut dev = Half4Wire::new(
+                pins[0].clone(),
+                pins[1].clone(),
+                pins[2].clone(),
+                pins[3].clone(),
+            );
+
+            // -1 wraps around to the last entry in the table
+            step_through(&mut dev, &[-1, 0, 1, 2]);
+
+            for mut pin in std::vec::Vec::from(pins) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn half_3_wire_sequence_crosses_zero() {
+            // positions -2, -1, 0, 1 map to 0b010, 0b110, 0b100, 0b101
+            let pins = [
+                expect_states(&[0, 0, 0, 1]),
+                expect_states(&[1, 1, 0, 0]),
+                expect_states(&[0, 1, 1, 1]),
+            ];
+            let mut dev = Half3Wire::new(
+                pins[0].clone(),
+                pins[1].clone(),
+                pins[2].clone(),
+            );
+
+            step_through(&mut dev, &[-2, -1, 0, 1]);
+
+            for mut pin in std::vec::Vec::from(pins) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn enable_pin_follows_the_outputs() {
+            let coils = [expect_states(&[0]), expect_states(&[0])];
+            let enable = expect_states(&[1, 0]);
+            let mut dev = WithEnablePin::new(
+                Full2Wire::new(coils[0].clone(), coils[1].clone()),
+                enable.clone(),
+            );
+
+            dev.enable_outputs().unwrap();
+            dev.disable_outputs().unwrap();
+
+            let (_, mut enable) = dev.into_inner();
+            enable.done();
+            for mut pin in std::vec::Vec::from(coils) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn inverted_step_and_enable_pins() {
+            let step = expect_states(&[0, 1, 1]);
+            let direction = expect_states(&[1, 0]);
+            let enable = expect_states(&[0, 1]);
+            let mut dev = WithEnablePin::new(
+                StepAndDirection::new(
+                    Inverted::new(step.clone()),
+                    direction.clone(),
+                    NoopDelay,
+                ),
+                Inverted::new(enable.clone()),
+            );
+
+            dev.enable_outputs().unwrap();
+            step_through(&mut dev, &[1]);
+            dev.disable_outputs().unwrap();
+
+            for mut pin in std::vec::Vec::from([step, direction, enable]) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn direction_is_correct_after_setting_the_position() {
+            let step = expect_states(&[1, 0]);
+            let direction = expect_states(&[1]);
+            let mut dev = StepAndDirection::new(
+                step.clone(),
+                direction.clone(),
+                NoopDelay,
+            );
+            let mut driver = crate::Driver::new();
+            driver.set_max_speed(100.0);
+            driver.set_acceleration(100.0);
+
+            driver.set_current_position(-5);
+            driver.move_to(-4);
+            let deadline = driver.next_step_time().unwrap();
+            driver.fire_step(&mut dev, deadline).unwrap();
+
+            assert_eq!(driver.current_position(), -4);
+            for mut pin in std::vec::Vec::from([step, direction]) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn pulse_widths_round_up_to_the_nearest_microsecond() {
+            let mut dev = StepAndDirection::new((), (), ());
+
+            dev.set_min_pulse_width(Duration::from_nanos(2500));
+            dev.set_direction_setup_time(Duration::from_nanos(200));
+
+            assert_eq!(dev.min_pulse_width(), Duration::from_micros(3));
+            assert_eq!(dev.direction_setup_time(), Duration::from_micros(1));
+        }
+
+        #[test]
+        fn full_3_wire_sequence() {
+            let pins = [
+                expect_states(&[0, 1, 0]),
+                expect_states(&[0, 0, 1]),
+                expect_states(&[1, 0, 0]),
+            ];
+            let mut dev = Full3Wire::new(
+                pins[0].clone(),
+                pins[1].clone(),
+                pins[2].clone(),
+            );
+
+            step_through(&mut dev, &[0, 1, 2]);
+
+            for mut pin in std::vec::Vec::from(pins) {
+                pin.done();
+            }
+        }
+
+        #[test]
+        fn half_4_wire_with_inverted_enable_pin() {
+            let pins = [
+                expect_states(&[1, 1, 0]),
+                expect_states(&[0, 0, 0]),
+                expect_states(&[0, 1, 0]),
+                expect_states(&[0, 0, 0]),
+            ];
+            let enable = expect_states(&[0, 1]);
+            let mut dev = WithEnablePin::new(
+                Half4Wire::new(
+                    pins[0].clone(),
+                    pins[1].clone(),
+                    pins[2].clone(),
+                    pins[3].clone(),
+                ),
+                Inverted::new(enable.clone()),
+            );
+
+            dev.enable_outputs().unwrap();
+            step_through(&mut dev, &[0, 1]);
+            dev.disable_outputs().unwrap();
+
+            for mut pin in std::vec::Vec::from(pins) {
+                pin.done();
+            }
+            let (_, Inverted(mut enable)) = dev.into_inner();
+            enable.done();
+        }
+
+        #[test]
+        fn home_then_move_with_step_and_direction() {
+            /// Keeps track of where the axis physically is, no matter what
+            /// the driver thinks its position is.
+            struct Tracked<'a, D>(D, &'a core::cell::Cell<i64>);
+
+            impl<'a, D: Device> Device for Tracked<'a, D> {
+                type Error = D::Error;
+
+                fn step(
+                    &mut self,
+                    ctx: &StepContext,
+                ) -> Result<(), Self::Error> {
+                    let delta = if ctx.forward { 1 } else { -1 };
+                    self.1.set(self.1.get() + delta);
+                    self.0.step(ctx)
+                }
+            }
+
+            // seek (2 steps back), back off (2 forward), approach (2 back),
+            // then one step forward once we're homed
+            let step = expect_states(&[1, 0].repeat(7));
+            let direction = expect_states(&[0, 1, 0, 1]);
+            let position = core::cell::Cell::new(0);
+            let mut dev = Tracked(
+                StepAndDirection::new(
+                    step.clone(),
+                    direction.clone(),
+                    NoopDelay,
+                ),
+                &position,
+            );
+            let mut switch = crate::func_limit_switch(|| position.get() <= -2);
+            let clock = crate::ManualClock::with_auto_advance(
+                Duration::from_micros(50),
+            );
+            let mut driver = crate::Driver::new();
+            driver.set_max_speed(1000.0);
+            driver.set_acceleration(1000.0);
+            let mut homing =
+                crate::Homing::new(crate::HomingDirection::Negative, 100);
+            homing.set_seek_speed(1000.0);
+            homing.set_approach_speed(500.0);
+            homing.set_back_off(2);
+
+            while homing
+                .poll(&mut driver, &mut dev, &mut switch, &clock)
+                .unwrap()
+                != crate::HomingState::Homed
+            {}
+            assert_eq!(driver.current_position(), 0);
+            assert_eq!(position.get(), -2);
+
+            // the driver's position was reset, but the direction pin still
+            // needs to change for the next step
+            driver.move_to(1);
+            while driver.is_running() {
+                driver.poll(&mut dev, &clock).unwrap();
+            }
+            assert_eq!(driver.current_position(), 1);
+            assert_eq!(position.get(), -1);
+
+            let (mut step, mut direction, _) = dev.0.into_inner();
+            step.done();
+            direction.done();
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) use hal_device_tests;
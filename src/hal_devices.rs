@@ -1,60 +1,41 @@
-use crate::{Device, StepContext};
-use embedded_hal::digital::v2::OutputPin;
-
-/// A [`Device`] which has step and direction pins.
-///
-/// Requires the `hal` feature.
-#[derive(Debug, Default, Clone, PartialEq)]
-pub struct StepAndDirection<Step, Direction> {
-    step: Step,
-    direction: Direction,
-}
+use crate::{Device, LimitSwitch, StepContext};
+use core::time::Duration;
+use embedded_hal::{
+    blocking::delay::DelayUs,
+    digital::v2::{InputPin, OutputPin},
+};
 
-impl<Step, Direction> StepAndDirection<Step, Direction> {
-    pub fn new(step: Step, direction: Direction) -> Self {
-        StepAndDirection { step, direction }
-    }
+pub use crate::hal_common::Inverted;
 
-    pub fn into_inner(self) -> (Step, Direction) { (self.step, self.direction) }
-}
+crate::hal_common::hal_devices!(feature = "hal", delay = DelayUs<u32>);
 
-fn set_output<P: OutputPin>(pin: &mut P, mask: u8) -> Result<(), P::Error> {
-    if mask != 0 {
-        pin.set_high()
-    } else {
-        pin.set_low()
-    }
-}
+impl<P: OutputPin> OutputPin for Inverted<P> {
+    type Error = P::Error;
 
-impl<Step, Direction, E> StepAndDirection<Step, Direction>
-where
-    Step: OutputPin<Error = E>,
-    Direction: OutputPin<Error = E>,
-{
-    fn set_output(&mut self, mask: u8) -> Result<(), E> {
-        set_output(&mut self.step, mask & 0b01)?;
-        set_output(&mut self.direction, mask & 0b10)?;
-
-        Ok(())
-    }
+    #[inline]
+    fn set_low(&mut self) -> Result<(), Self::Error> { self.0.set_high() }
+
+    #[inline]
+    fn set_high(&mut self) -> Result<(), Self::Error> { self.0.set_low() }
 }
 
-impl<Step, Direction, E> Device for StepAndDirection<Step, Direction>
-where
-    Step: OutputPin<Error = E>,
-    Direction: OutputPin<Error = E>,
-{
-    type Error = E;
+impl<P: InputPin> InputPin for Inverted<P> {
+    type Error = P::Error;
 
     #[inline]
-    fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
-        // copied straight from AccelStepper::step2()
-        match ctx.position & 0x03 {
-            0 => self.set_output(0b10),
-            1 => self.set_output(0b11),
-            2 => self.set_output(0b01),
-            3 => self.set_output(0b00),
-            _ => unreachable!(),
-        }
-    }
+    fn is_high(&self) -> Result<bool, Self::Error> { self.0.is_low() }
+
+    #[inline]
+    fn is_low(&self) -> Result<bool, Self::Error> { self.0.is_high() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh0::{
+        delay::NoopDelay,
+        digital::{Mock, State, Transaction},
+    };
+
+    crate::hal_common::hal_device_tests!();
 }
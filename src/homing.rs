@@ -0,0 +1,432 @@
+use crate::{Device, Driver, SystemClock};
+use void::Void;
+
+/// A switch which is triggered when an axis reaches the end of its travel.
+///
+/// With the `hal` or `embedded-hal-1` features, an `InputPin` can be used as
+/// a limit switch by wrapping it in a `LimitSwitchPin`.
+pub trait LimitSwitch {
+    /// The type of error that may be encountered when reading the switch.
+    type Error;
+
+    /// Is the switch currently triggered?
+    fn is_triggered(&mut self) -> Result<bool, Self::Error>;
+}
+
+impl<L: LimitSwitch> LimitSwitch for &mut L {
+    type Error = L::Error;
+
+    fn is_triggered(&mut self) -> Result<bool, Self::Error> {
+        (*self).is_triggered()
+    }
+}
+
+/// A [`LimitSwitch`] which calls a function to see whether it is triggered.
+pub fn func_limit_switch<F>(is_triggered: F) -> impl LimitSwitch<Error = Void>
+where
+    F: FnMut() -> bool,
+{
+    FuncLimitSwitch(is_triggered)
+}
+
+struct FuncLimitSwitch<F>(F);
+
+impl<F: FnMut() -> bool> LimitSwitch for FuncLimitSwitch<F> {
+    type Error = Void;
+
+    #[inline]
+    fn is_triggered(&mut self) -> Result<bool, Self::Error> { Ok((self.0)()) }
+}
+
+/// Which way to move when looking for the limit switch.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HomingDirection {
+    /// Towards negative positions.
+    Negative,
+    /// Towards positive positions.
+    Positive,
+}
+
+/// How far through the homing sequence we are.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HomingState {
+    /// Moving towards the limit switch at the seek speed.
+    Seeking,
+    /// Moving away from the limit switch until it is released.
+    BackingOff,
+    /// Slowly moving back towards the limit switch to find its exact
+    /// position.
+    Approaching,
+    /// The switch was found and the [`Driver`]'s position has been set.
+    Homed,
+    /// The switch couldn't be found within the maximum travel.
+    SwitchNotFound,
+    /// The switch was still triggered after backing off by the maximum
+    /// travel.
+    SwitchStuck,
+}
+
+/// Reasons homing may fail.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HomingError<D, L> {
+    /// The [`Device`] failed to take a step.
+    Device(D),
+    /// The [`LimitSwitch`] couldn't be read.
+    Switch(L),
+    /// The switch wasn't triggered within the maximum travel.
+    SwitchNotFound,
+    /// The switch was still triggered after backing off by the maximum
+    /// travel.
+    SwitchStuck,
+}
+
+/// A non-blocking state machine for finding an axis's zero position using a
+/// [`LimitSwitch`].
+///
+/// Homing happens in three stages:
+///
+/// 1. Move towards the switch at the [`Homing::seek_speed()`] until it is
+///    triggered
+/// 2. Back away from the switch at the [`Homing::approach_speed()`] until it is
+///    released and we have moved at least [`Homing::back_off()`] steps
+/// 3. Slowly approach the switch again at the [`Homing::approach_speed()`],
+///    then set the [`Driver`]'s current position to the [`Homing::offset()`] as
+///    soon as it is triggered
+///
+/// Each stage uses a constant speed, so the [`Driver`]'s maximum speed must
+/// be at least as fast as the seek speed, and the seek speed should be slow
+/// enough for the motor to stop instantly without losing steps.
+///
+/// # Examples
+///
+/// ```rust
+/// use accel_stepper::{
+///     Driver, Homing, HomingDirection, HomingState, WrappingClock,
+/// };
+/// use core::cell::Cell;
+///
+/// let mut axis = Driver::new();
+/// axis.set_max_speed(500.0);
+///
+/// // the switch is 1234 steps away from where we start
+/// let position = Cell::new(0);
+/// let mut dev = accel_stepper::func_device(
+///     || position.set(position.get() + 1),
+///     || position.set(position.get() - 1),
+/// );
+/// let mut switch = accel_stepper::func_limit_switch(|| position.get() <= -1234);
+/// // a microsecond timer which moves on a little every time it is read
+/// let ticks = Cell::new(0_u32);
+/// let clock = WrappingClock::new(1_000_000, || {
+///     ticks.set(ticks.get().wrapping_add(100));
+///     ticks.get()
+/// });
+///
+/// let mut homing = Homing::new(HomingDirection::Negative, 5000);
+/// homing.set_offset(-10);
+///
+/// while homing.poll(&mut axis, &mut dev, &mut switch, &clock).unwrap()
+///     != HomingState::Homed
+/// {
+///     // do other things while homing
+/// }
+///
+/// assert_eq!(axis.current_position(), -10);
+/// assert_eq!(position.get(), -1234);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Homing {
+    direction: HomingDirection,
+    max_travel: u64,
+    seek_speed: f32,
+    approach_speed: f32,
+    back_off: u64,
+    offset: i64,
+    state: Option<HomingState>,
+    /// Where the current stage started.
+    stage_started_at: i64,
+}
+
+impl Homing {
+    /// Create a new [`Homing`] sequence which will move in the provided
+    /// direction, failing if the switch isn't found within `max_travel`
+    /// steps.
+    pub fn new(direction: HomingDirection, max_travel: u64) -> Homing {
+        Homing {
+            direction,
+            max_travel,
+            seek_speed: 200.0,
+            approach_speed: 20.0,
+            back_off: 50,
+            offset: 0,
+            state: None,
+            stage_started_at: 0,
+        }
+    }
+
+    /// Which way we move to find the switch.
+    pub fn direction(&self) -> HomingDirection { self.direction }
+
+    /// The furthest we may move while looking for the switch.
+    pub fn max_travel(&self) -> u64 { self.max_travel }
+
+    /// Set the speed used when initially looking for the switch, in
+    /// `steps/second`.
+    pub fn set_seek_speed(&mut self, speed: f32) {
+        debug_assert!(speed > 0.0);
+        self.seek_speed = speed;
+    }
+
+    /// Get the seek speed.
+    pub fn seek_speed(&self) -> f32 { self.seek_speed }
+
+    /// Set the speed used when backing off and re-approaching the switch, in
+    /// `steps/second`.
+    pub fn set_approach_speed(&mut self, speed: f32) {
+        debug_assert!(speed > 0.0);
+        self.approach_speed = speed;
+    }
+
+    /// Get the approach speed.
+    pub fn approach_speed(&self) -> f32 { self.approach_speed }
+
+    /// Set the minimum number of steps to back away from the switch before
+    /// re-approaching it.
+    pub fn set_back_off(&mut self, steps: u64) { self.back_off = steps; }
+
+    /// Get the back-off distance.
+    pub fn back_off(&self) -> u64 { self.back_off }
+
+    /// Set the position assigned to the point where the switch triggers.
+    pub fn set_offset(&mut self, offset: i64) { self.offset = offset; }
+
+    /// Get the offset.
+    pub fn offset(&self) -> i64 { self.offset }
+
+    /// How far through the homing sequence we are, or `None` if it hasn't
+    /// been started.
+    pub fn state(&self) -> Option<HomingState> { self.state }
+
+    /// Start again from the beginning the next time [`Homing::poll()`] is
+    /// called.
+    pub fn reset(&mut self) { self.state = None; }
+
+    /// Check the switch and step the motor if necessary, returning the state
+    /// we are now in.
+    ///
+    /// Once the sequence has finished, polling has no effect and will keep
+    /// returning [`HomingState::Homed`] (or the same error) until
+    /// [`Homing::reset()`] is called.
+    pub fn poll<D, L, C>(
+        &mut self,
+        driver: &mut Driver,
+        device: D,
+        mut switch: L,
+        clock: C,
+    ) -> Result<HomingState, HomingError<D::Error, L::Error>>
+    where
+        D: Device,
+        L: LimitSwitch,
+        C: SystemClock,
+    {
+        let state = match self.state {
+            Some(HomingState::Homed) => return Ok(HomingState::Homed),
+            Some(HomingState::SwitchNotFound) => {
+                return Err(HomingError::SwitchNotFound)
+            },
+            Some(HomingState::SwitchStuck) => {
+                return Err(HomingError::SwitchStuck)
+            },
+            Some(state) => state,
+            None => {
+                self.enter(driver, HomingState::Seeking);
+                HomingState::Seeking
+            },
+        };
+
+        let triggered = switch.is_triggered().map_err(HomingError::Switch)?;
+        let travelled =
+            (driver.current_position() - self.stage_started_at).unsigned_abs();
+
+        match state {
+            HomingState::Seeking | HomingState::Approaching if triggered => {
+                if state == HomingState::Seeking {
+                    self.enter(driver, HomingState::BackingOff);
+                } else {
+                    driver.set_current_position(self.offset);
+                    self.state = Some(HomingState::Homed);
+                    return Ok(HomingState::Homed);
+                }
+            },
+            HomingState::BackingOff
+                if !triggered && travelled >= self.back_off =>
+            {
+                self.enter(driver, HomingState::Approaching);
+            },
+            _ if travelled >= self.max_travel => {
+                driver.set_current_position(driver.current_position());
+                let (state, error) = if state == HomingState::BackingOff {
+                    (HomingState::SwitchStuck, HomingError::SwitchStuck)
+                } else {
+                    (HomingState::SwitchNotFound, HomingError::SwitchNotFound)
+                };
+                self.state = Some(state);
+                return Err(error);
+            },
+            _ => {},
+        }
+
+        driver
+            .poll_at_constant_speed(device, clock)
+            .map_err(HomingError::Device)?;
+
+        Ok(self.state.unwrap_or(HomingState::Seeking))
+    }
+
+    /// Start a new stage of the homing sequence.
+    fn enter(&mut self, driver: &mut Driver, state: HomingState) {
+        let towards_switch = match self.direction {
+            HomingDirection::Negative => -1.0,
+            HomingDirection::Positive => 1.0,
+        };
+        let speed = match state {
+            HomingState::Seeking => self.seek_speed * towards_switch,
+            HomingState::BackingOff => -self.approach_speed * towards_switch,
+            _ => self.approach_speed * towards_switch,
+        };
+
+        // stop immediately, then start moving at the new speed
+        let position = driver.current_position();
+        driver.set_current_position(position);
+        driver.set_speed(speed);
+
+        self.stage_started_at = position;
+        self.state = Some(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ManualClock;
+    use core::{cell::Cell, time::Duration};
+    use std::vec::Vec;
+
+    /// A simulated axis which starts at zero, with a switch that is
+    /// triggered at some positions.
+    struct Axis {
+        position: Cell<i64>,
+        is_triggered: fn(i64) -> bool,
+        /// The position at every poll.
+        history: Vec<i64>,
+    }
+
+    impl Axis {
+        fn new(is_triggered: fn(i64) -> bool) -> Axis {
+            Axis {
+                position: Cell::new(0),
+                is_triggered,
+                history: Vec::new(),
+            }
+        }
+
+        fn home(
+            &mut self,
+            homing: &mut Homing,
+            driver: &mut Driver,
+        ) -> Result<HomingState, HomingError<Void, Void>> {
+            let clock =
+                ManualClock::with_auto_advance(Duration::from_micros(50));
+            let position = &self.position;
+            let is_triggered = self.is_triggered;
+            let history = &mut self.history;
+
+            let mut dev = crate::func_device(
+                || position.set(position.get() + 1),
+                || position.set(position.get() - 1),
+            );
+            let mut switch = func_limit_switch(|| {
+                history.push(position.get());
+                is_triggered(position.get())
+            });
+
+            while homing.poll(driver, &mut dev, &mut switch, &clock)?
+                != HomingState::Homed
+            {}
+
+            Ok(HomingState::Homed)
+        }
+    }
+
+    fn driver() -> Driver {
+        let mut driver = Driver::new();
+        driver.set_max_speed(1000.0);
+        driver
+    }
+
+    #[test]
+    fn seek_back_off_and_approach() {
+        let mut axis = Axis::new(|pos| pos <= -300);
+        let mut driver = driver();
+        let mut homing = Homing::new(HomingDirection::Negative, 1000);
+        homing.set_seek_speed(1000.0);
+        homing.set_offset(5);
+
+        assert_eq!(axis.home(&mut homing, &mut driver), Ok(HomingState::Homed));
+
+        assert_eq!(driver.current_position(), 5);
+        assert_eq!(axis.position.get(), -300);
+        // we backed off by at least 50 steps before coming back
+        let first_trigger =
+            axis.history.iter().position(|&pos| pos == -300).unwrap();
+        let furthest_back_off =
+            axis.history[first_trigger..].iter().max().unwrap();
+        assert!(*furthest_back_off >= -250);
+        assert_eq!(axis.history.iter().min(), Some(&-300));
+
+        // polling again doesn't move anything
+        assert_eq!(axis.home(&mut homing, &mut driver), Ok(HomingState::Homed));
+        assert_eq!(axis.position.get(), -300);
+        assert!(!driver.is_running());
+    }
+
+    #[test]
+    fn stuck_switch() {
+        let mut axis = Axis::new(|_| true);
+        let mut driver = driver();
+        let mut homing = Homing::new(HomingDirection::Positive, 1000);
+        homing.set_approach_speed(500.0);
+
+        assert_eq!(
+            axis.home(&mut homing, &mut driver),
+            Err(HomingError::SwitchStuck)
+        );
+        assert_eq!(homing.state(), Some(HomingState::SwitchStuck));
+        // we tried to back off in the negative direction
+        assert_eq!(axis.position.get(), -1000);
+        assert!(!driver.is_running());
+    }
+
+    #[test]
+    fn give_up_after_the_maximum_travel() {
+        let mut axis = Axis::new(|_| false);
+        let mut driver = driver();
+        let mut homing = Homing::new(HomingDirection::Negative, 500);
+        homing.set_seek_speed(1000.0);
+
+        assert_eq!(
+            axis.home(&mut homing, &mut driver),
+            Err(HomingError::SwitchNotFound)
+        );
+        assert_eq!(axis.position.get(), -500);
+        assert!(!driver.is_running());
+
+        // the failure is sticky until we reset
+        assert_eq!(
+            axis.home(&mut homing, &mut driver),
+            Err(HomingError::SwitchNotFound)
+        );
+        homing.reset();
+        assert_eq!(homing.state(), None);
+    }
+}
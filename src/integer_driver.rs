@@ -0,0 +1,362 @@
+use crate::{utils::isqrt, Device, StepContext, SystemClock};
+use core::time::Duration;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+/// The number of fractional bits used when storing step sizes, so rounding
+/// errors don't accumulate when applying the step size recurrence.
+const FRACTIONAL_BITS: u32 = 8;
+
+/// A stepper motor driver which only uses integer arithmetic.
+///
+/// This implements the same acceleration profile as [`crate::Driver`] (David
+/// Austin's "Generate stepper-motor speed profiles in real time"), but all
+/// calculations are done using fixed-point nanoseconds. It is intended for
+/// processors without a floating point unit (e.g. Cortex-M0 or AVR), where
+/// the `f32` maths done by [`crate::Driver`] is prohibitively slow.
+///
+/// Speeds are measured in whole `steps/second` and accelerations in whole
+/// `steps/second/second`.
+#[derive(Debug, Default, PartialEq)]
+pub struct IntegerDriver {
+    max_speed: u32,
+    acceleration: u32,
+    current_position: i64,
+    target_position: i64,
+    forward: bool,
+    /// The current step interval in nanoseconds, or `0` when stopped.
+    step_interval: u64,
+    last_step_time: Duration,
+
+    /// The step counter for speed calculations
+    step_counter: i64,
+    /// Step sizes, in fixed-point nanoseconds.
+    initial_step_size: u64,
+    last_step_size: u64,
+    /// Min step size based on `max_speed`.
+    min_step_size: u64,
+}
+
+impl IntegerDriver {
+    pub fn new() -> IntegerDriver {
+        let mut d = IntegerDriver::default();
+
+        // Set up some non-zero defaults so we can immediately run at constant
+        // speeds
+        d.set_max_speed(1);
+        d.set_acceleration(1);
+
+        d
+    }
+
+    /// Move to the specified location relative to the zero point (typically
+    /// set when calibrating using [`IntegerDriver::set_current_position()`]).
+    #[inline]
+    pub fn move_to(&mut self, location: i64) {
+        if self.target_position() != location {
+            self.target_position = location;
+            self.compute_new_speed();
+        }
+    }
+
+    /// Move forward by the specified number of steps.
+    #[inline]
+    pub fn move_by(&mut self, delta: i64) {
+        self.move_to(self.current_position() + delta);
+    }
+
+    /// Set the maximum permitted speed in `steps/second`.
+    #[inline]
+    pub fn set_max_speed(&mut self, steps_per_second: u32) {
+        debug_assert!(steps_per_second > 0);
+
+        self.max_speed = steps_per_second;
+        self.min_step_size = (NANOS_PER_SEC << FRACTIONAL_BITS)
+            / u64::from(steps_per_second.max(1));
+    }
+
+    /// Get the maximum speed.
+    #[inline]
+    pub fn max_speed(&self) -> u32 { self.max_speed }
+
+    /// Set the acceleration/deceleration rate (in `steps/sec/sec`).
+    #[inline]
+    pub fn set_acceleration(&mut self, acceleration: u32) {
+        if acceleration == 0 || acceleration == self.acceleration {
+            return;
+        }
+
+        // Recompute step_counter per Equation 17
+        self.step_counter = self.step_counter * i64::from(self.acceleration)
+            / i64::from(acceleration);
+        // New initial_step_size per Equation 7, with correction per
+        // Equation 15
+        let initial_step_size =
+            isqrt(2 * NANOS_PER_SEC * NANOS_PER_SEC / u64::from(acceleration))
+                * 676
+                / 1000;
+        self.initial_step_size = initial_step_size << FRACTIONAL_BITS;
+        self.acceleration = acceleration;
+        self.compute_new_speed();
+    }
+
+    /// Get the acceleration/deceleration rate.
+    #[inline]
+    pub fn acceleration(&self) -> u32 { self.acceleration }
+
+    /// Get the time between steps at the current speed, or zero if the motor
+    /// is stopped.
+    #[inline]
+    pub fn step_interval(&self) -> Duration {
+        Duration::from_nanos(self.step_interval)
+    }
+
+    /// Get the number of steps to go until reaching the target position.
+    #[inline]
+    pub fn distance_to_go(&self) -> i64 {
+        self.target_position() - self.current_position()
+    }
+
+    /// Get the most recently set target position.
+    #[inline]
+    pub fn target_position(&self) -> i64 { self.target_position }
+
+    /// Reset the current motor position so the current location is considered
+    /// the new `0` position.
+    #[inline]
+    pub fn set_current_position(&mut self, position: i64) {
+        self.current_position = position;
+        self.target_position = position;
+        self.step_interval = 0;
+        self.step_counter = 0;
+    }
+
+    /// Get the current motor position, as measured by counting the number of
+    /// pulses emitted.
+    #[inline]
+    pub fn current_position(&self) -> i64 { self.current_position }
+
+    /// Sets a new target position that causes the stepper to stop as quickly as
+    /// possible, using the current speed and acceleration parameters.
+    #[inline]
+    pub fn stop(&mut self) {
+        if self.step_interval == 0 {
+            return;
+        }
+
+        let steps_to_stop = self.steps_to_stop() + 1;
+
+        if self.forward {
+            self.move_by(steps_to_stop);
+        } else {
+            self.move_by(-steps_to_stop);
+        }
+    }
+
+    /// Checks to see if the motor is currently running to a target.
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        self.step_interval != 0
+            || self.target_position() != self.current_position()
+    }
+
+    /// The number of steps needed to stop from the current speed (Equation
+    /// 16), rounded to the nearest step.
+    fn steps_to_stop(&self) -> i64 {
+        if self.step_interval == 0 {
+            return 0;
+        }
+
+        // speed in steps/second, with 8 fractional bits
+        let speed = (NANOS_PER_SEC << FRACTIONAL_BITS) / self.step_interval;
+        let speed_squared = speed.saturating_mul(speed);
+        let denominator =
+            u64::from(self.acceleration) << (2 * FRACTIONAL_BITS + 1);
+
+        ((speed_squared + denominator / 2) / denominator) as i64
+    }
+
+    fn compute_new_speed(&mut self) {
+        let distance_to = self.distance_to_go();
+        let steps_to_stop = self.steps_to_stop();
+
+        if distance_to == 0 && steps_to_stop <= 1 {
+            // We are at the target and its time to stop
+            self.step_interval = 0;
+            self.step_counter = 0;
+            return;
+        }
+
+        if distance_to > 0 {
+            // the target is in front of us
+            if self.step_counter > 0 {
+                // Currently accelerating, need to decel now? Or maybe going the
+                // wrong way?
+                if steps_to_stop >= distance_to || !self.forward {
+                    self.step_counter = -steps_to_stop;
+                }
+            } else if self.step_counter < 0 {
+                // Currently decelerating, need to accel again?
+                if steps_to_stop < distance_to && self.forward {
+                    self.step_counter = -self.step_counter;
+                }
+            }
+        } else if distance_to < 0 {
+            // the target is behind us
+            if self.step_counter > 0 {
+                // Currently accelerating, need to decel now? Or maybe going the
+                // wrong way?
+                if steps_to_stop >= -distance_to || self.forward {
+                    self.step_counter = -steps_to_stop;
+                }
+            } else if self.step_counter < 0 {
+                // currently decelerating, need to accel again?
+                if steps_to_stop < -distance_to && !self.forward {
+                    self.step_counter = -self.step_counter;
+                }
+            }
+        }
+
+        if self.step_counter == 0 {
+            // This is the first step after having stopped
+            self.last_step_size = self.initial_step_size;
+            self.forward = distance_to > 0;
+        } else {
+            // Subsequent step. Works for accel (n is +_ve) and decel (n is
+            // -ve).
+            let last_step_size = self.last_step_size as i64;
+            let last_step_size = last_step_size
+                - last_step_size * 2 / (4 * self.step_counter + 1);
+            self.last_step_size =
+                (last_step_size as u64).max(self.min_step_size);
+        }
+
+        self.step_counter += 1;
+        self.step_interval = self.last_step_size >> FRACTIONAL_BITS;
+    }
+
+    /// Poll the driver and step it if a step is due.
+    ///
+    /// This function must called as frequently as possoble, but at least once
+    /// per minimum step time interval, preferably as part of the main loop.
+    pub fn poll<C, D>(
+        &mut self,
+        mut device: D,
+        clock: C,
+    ) -> Result<(), D::Error>
+    where
+        C: SystemClock,
+        D: Device,
+    {
+        // Dont do anything unless we actually have a step interval
+        if self.step_interval == 0 {
+            return Ok(());
+        }
+
+        let now = clock.elapsed();
+
+        if now < self.last_step_time {
+            // the clock went backwards, so start timing the next step from
+            // here
+            self.last_step_time = now;
+        }
+
+        if now - self.last_step_time >= Duration::from_nanos(self.step_interval)
+        {
+            let new_position = if self.forward {
+                self.current_position + 1
+            } else {
+                self.current_position - 1
+            };
+
+            let ctx = StepContext {
+                position: new_position,
+                forward: self.forward,
+                step_time: now,
+            };
+            device.step(&ctx)?;
+
+            self.current_position = new_position;
+            self.last_step_time = now;
+            self.compute_new_speed();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support::Recorder, Driver, ManualClock};
+
+    #[test]
+    fn step_timings_match_the_floating_point_driver() {
+        let tick = Duration::from_micros(10);
+
+        let float_clock = ManualClock::with_auto_advance(tick);
+        let mut float_driver = Driver::new();
+        float_driver.set_max_speed(500.0);
+        float_driver.set_acceleration(1000.0);
+        float_driver.move_to(500);
+        let mut expected = Recorder::default();
+        while float_driver.is_running() {
+            float_driver.poll(&mut expected, &float_clock).unwrap();
+        }
+        let expected = expected.times();
+
+        let int_clock = ManualClock::with_auto_advance(tick);
+        let mut int_driver = IntegerDriver::new();
+        int_driver.set_max_speed(500);
+        int_driver.set_acceleration(1000);
+        int_driver.move_to(500);
+        let mut got = Recorder::default();
+        while int_driver.is_running() {
+            int_driver.poll(&mut got, &int_clock).unwrap();
+        }
+        let got = got.times();
+
+        assert_eq!(int_driver.current_position(), 500);
+        assert_eq!(got.len(), expected.len());
+
+        // allow a couple of clock ticks' jitter, plus a small fraction of the
+        // total time to account for accumulated rounding errors
+        for (i, (got, expected)) in got.iter().zip(&expected).enumerate() {
+            let diff = if got > expected {
+                *got - *expected
+            } else {
+                *expected - *got
+            };
+            let tolerance = tick * 2 + *expected / 200;
+            assert!(
+                diff <= tolerance,
+                "step {}: {:?} vs {:?}",
+                i,
+                got,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn stop_from_full_speed() {
+        let clock = ManualClock::with_auto_advance(Duration::from_micros(50));
+        let mut driver = IntegerDriver::new();
+        driver.set_max_speed(200);
+        driver.set_acceleration(400);
+        driver.move_to(10_000);
+
+        let mut device = Recorder::default();
+        while driver.current_position() < 200 {
+            driver.poll(&mut device, &clock).unwrap();
+        }
+        driver.stop();
+        // v^2 / 2a = 200^2 / 800 = 50 steps to stop, plus one for rounding
+        assert_eq!(driver.target_position(), 251);
+
+        while driver.is_running() {
+            driver.poll(&mut device, &clock).unwrap();
+        }
+        assert_eq!(driver.current_position(), 251);
+        assert_eq!(device.steps.len(), 251);
+    }
+}
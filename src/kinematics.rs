@@ -0,0 +1,477 @@
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use libm::F32Ext;
+
+use crate::QueueFull;
+use core::f32::consts::PI;
+
+/// The mapping between a machine's Cartesian coordinates and the positions of
+/// its motors.
+///
+/// Cartesian coordinates are measured in steps, where one unit is the distance
+/// a Cartesian axis moves for a single step of its motor. Any motors beyond the
+/// ones used by a particular machine layout are passed straight through, so
+/// (for example) a CoreXY gantry's `Z` axis and extruder can be driven by the
+/// third and fourth motors.
+///
+/// Implementations are used by [`crate::MultiDriver::move_to_cartesian()`]
+/// and [`crate::MultiDriver::enqueue_cartesian()`]. If there are too few
+/// motors for the machine layout (e.g. a [`CoreXY`] gantry with only one
+/// motor), every position is [`Unreachable`].
+pub trait Kinematics {
+    /// The number of Cartesian axes when driving `motors` motors.
+    fn cartesian_axes(&self, motors: usize) -> usize;
+
+    /// Find the motor positions for a Cartesian position (inverse kinematics).
+    fn to_motors(
+        &self,
+        position: &[f32],
+        motors: &mut [f32],
+    ) -> Result<(), Unreachable>;
+
+    /// Find the Cartesian position for a set of motor positions (forward
+    /// kinematics).
+    fn to_cartesian(
+        &self,
+        motors: &[f32],
+        position: &mut [f32],
+    ) -> Result<(), Unreachable>;
+
+    /// The longest piece (in steps) a straight Cartesian line may be split
+    /// into, or `None` if straight lines in Cartesian space are also straight
+    /// lines for the motors.
+    fn max_segment_length(&self) -> Option<f32> { None }
+}
+
+/// The requested position can't be reached by the machine.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Unreachable;
+
+/// Reasons a Cartesian move couldn't be added to the queue.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MoveError {
+    /// There is no room left in the queue.
+    QueueFull,
+    /// Part of the move can't be reached by the machine.
+    Unreachable,
+}
+
+impl From<QueueFull> for MoveError {
+    fn from(_: QueueFull) -> MoveError { MoveError::QueueFull }
+}
+
+impl From<Unreachable> for MoveError {
+    fn from(_: Unreachable) -> MoveError { MoveError::Unreachable }
+}
+
+/// Every axis is driven directly by its own motor.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Cartesian;
+
+impl Kinematics for Cartesian {
+    fn cartesian_axes(&self, motors: usize) -> usize { motors }
+
+    fn to_motors(
+        &self,
+        position: &[f32],
+        motors: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        motors.copy_from_slice(position);
+        Ok(())
+    }
+
+    fn to_cartesian(
+        &self,
+        motors: &[f32],
+        position: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        position.copy_from_slice(motors);
+        Ok(())
+    }
+}
+
+/// A CoreXY gantry, where the `X` and `Y` axes are moved by two motors
+/// working together through a pair of crossed belts.
+///
+/// The first motor (`A`) moves by `X + Y` and the second (`B`) by `X - Y`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CoreXY;
+
+impl Kinematics for CoreXY {
+    fn cartesian_axes(&self, motors: usize) -> usize { motors }
+
+    fn to_motors(
+        &self,
+        position: &[f32],
+        motors: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        if position.len() < 2 {
+            return Err(Unreachable);
+        }
+
+        motors.copy_from_slice(position);
+        motors[0] = position[0] + position[1];
+        motors[1] = position[0] - position[1];
+        Ok(())
+    }
+
+    fn to_cartesian(
+        &self,
+        motors: &[f32],
+        position: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        if motors.len() < 2 {
+            return Err(Unreachable);
+        }
+
+        position.copy_from_slice(motors);
+        position[0] = (motors[0] + motors[1]) / 2.0;
+        position[1] = (motors[0] - motors[1]) / 2.0;
+        Ok(())
+    }
+}
+
+/// An H-bot gantry, where a single H-shaped belt is moved by two stationary
+/// motors.
+///
+/// The motors move in the same way as [`CoreXY`], with the first motor moving
+/// by `X + Y` and the second by `X - Y`. The difference is purely mechanical
+/// (an H-bot's belt tension tends to twist the gantry), so it has its own type
+/// to make machine configurations self-documenting.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HBot;
+
+impl Kinematics for HBot {
+    fn cartesian_axes(&self, motors: usize) -> usize { motors }
+
+    fn to_motors(
+        &self,
+        position: &[f32],
+        motors: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        CoreXY.to_motors(position, motors)
+    }
+
+    fn to_cartesian(
+        &self,
+        motors: &[f32],
+        position: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        CoreXY.to_cartesian(motors, position)
+    }
+}
+
+/// A gantry where one axis is driven by two motors, one on each side.
+///
+/// The extra motor is always the last one, so a machine with `X`, `Y` and `Z`
+/// axes and a dual `Y` gantry would have the motors `X`, `Y`, `Z` and `Y2`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DualGantry {
+    axis: usize,
+}
+
+impl DualGantry {
+    /// Create a new [`DualGantry`] where the axis with the provided index is
+    /// also driven by the last motor.
+    pub const fn new(axis: usize) -> DualGantry { DualGantry { axis } }
+
+    /// The index of the axis driven by two motors.
+    pub fn axis(&self) -> usize { self.axis }
+}
+
+impl Kinematics for DualGantry {
+    fn cartesian_axes(&self, motors: usize) -> usize {
+        motors.saturating_sub(1)
+    }
+
+    fn to_motors(
+        &self,
+        position: &[f32],
+        motors: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        let (last, rest) = motors.split_last_mut().ok_or(Unreachable)?;
+        rest.copy_from_slice(position);
+        *last = *position.get(self.axis).ok_or(Unreachable)?;
+        Ok(())
+    }
+
+    fn to_cartesian(
+        &self,
+        motors: &[f32],
+        position: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        // both motors should always be in the same place, so we only need
+        // to look at the first one
+        let (_, rest) = motors.split_last().ok_or(Unreachable)?;
+
+        if self.axis >= rest.len() {
+            return Err(Unreachable);
+        }
+
+        position.copy_from_slice(rest);
+        Ok(())
+    }
+}
+
+/// A linear delta, where the effector hangs from three carriages riding up
+/// and down vertical towers by pairs of arms of equal length.
+///
+/// The towers are evenly spaced around a circle centred on the origin, at
+/// 210° (front left), 330° (front right), and 90° (back). The first three
+/// motors move the carriages, and their positions are each carriage's height
+/// measured in the same coordinate system as the effector's `Z` (so the
+/// carriages are always above the effector).
+///
+/// Straight lines in Cartesian space become curves for the carriages, so
+/// lines are split into pieces no longer than
+/// [`LinearDelta::segment_length()`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LinearDelta {
+    arm_length: f32,
+    towers: [(f32, f32); 3],
+    segment_length: f32,
+}
+
+impl LinearDelta {
+    /// Create a new [`LinearDelta`] given the length of its arms and the
+    /// horizontal distance between the centre of the effector and each
+    /// tower's carriage when the effector is at the origin.
+    pub fn new(arm_length: f32, radius: f32) -> LinearDelta {
+        let tower = |degrees: f32| {
+            let angle = degrees * PI / 180.0;
+            (radius * angle.cos(), radius * angle.sin())
+        };
+
+        LinearDelta {
+            arm_length,
+            towers: [tower(210.0), tower(330.0), tower(90.0)],
+            segment_length: arm_length / 100.0,
+        }
+    }
+
+    /// The length of each arm.
+    pub fn arm_length(&self) -> f32 { self.arm_length }
+
+    /// The `(X, Y)` position of each tower.
+    pub fn towers(&self) -> [(f32, f32); 3] { self.towers }
+
+    /// Set the longest piece (in steps) straight lines are split into.
+    pub fn set_segment_length(&mut self, length: f32) {
+        debug_assert!(length > 0.0);
+        self.segment_length = length;
+    }
+
+    /// Get the segment length.
+    pub fn segment_length(&self) -> f32 { self.segment_length }
+}
+
+impl Kinematics for LinearDelta {
+    fn cartesian_axes(&self, motors: usize) -> usize { motors }
+
+    fn to_motors(
+        &self,
+        position: &[f32],
+        motors: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        if position.len() < 3 {
+            return Err(Unreachable);
+        }
+
+        motors.copy_from_slice(position);
+        let (x, y, z) = (position[0], position[1], position[2]);
+
+        for (motor, &(tower_x, tower_y)) in motors.iter_mut().zip(&self.towers)
+        {
+            let (dx, dy) = (x - tower_x, y - tower_y);
+            let height_squared =
+                self.arm_length * self.arm_length - dx * dx - dy * dy;
+
+            if height_squared < 0.0 {
+                return Err(Unreachable);
+            }
+
+            *motor = z + height_squared.sqrt();
+        }
+
+        Ok(())
+    }
+
+    fn to_cartesian(
+        &self,
+        motors: &[f32],
+        position: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        if motors.len() < 3 {
+            return Err(Unreachable);
+        }
+
+        position.copy_from_slice(motors);
+
+        // The effector is where three spheres centred on the carriages
+        // intersect, so use trilateration to find it
+        let carriage = |i: usize| {
+            let (x, y) = self.towers[i];
+            [x, y, motors[i]]
+        };
+        let (p1, p2, p3) = (carriage(0), carriage(1), carriage(2));
+
+        let p12 = sub(p2, p1);
+        let d = norm(p12);
+        let ex = scale(p12, 1.0 / d);
+        let p13 = sub(p3, p1);
+        let i = dot(ex, p13);
+        let ey = sub(p13, scale(ex, i));
+        let ey = scale(ey, 1.0 / norm(ey));
+        let ez = cross(ex, ey);
+        let j = dot(ey, p13);
+
+        // every sphere has the same radius, which simplifies things a bit
+        let x = d / 2.0;
+        let y = (i * i + j * j) / (2.0 * j) - i * x / j;
+        let z_squared = self.arm_length * self.arm_length - x * x - y * y;
+
+        if z_squared < 0.0 {
+            return Err(Unreachable);
+        }
+
+        // the effector hangs below the carriages
+        let z = if ez[2] > 0.0 {
+            -z_squared.sqrt()
+        } else {
+            z_squared.sqrt()
+        };
+        let effector =
+            add(p1, add(scale(ex, x), add(scale(ey, y), scale(ez, z))));
+
+        position[..3].copy_from_slice(&effector);
+        Ok(())
+    }
+
+    fn max_segment_length(&self) -> Option<f32> { Some(self.segment_length) }
+}
+
+type Vector = [f32; 3];
+
+fn add(a: Vector, b: Vector) -> Vector {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: Vector, b: Vector) -> Vector {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: Vector, factor: f32) -> Vector {
+    [a[0] * factor, a[1] * factor, a[2] * factor]
+}
+
+fn dot(a: Vector, b: Vector) -> f32 { a[0] * b[0] + a[1] * b[1] + a[2] * b[2] }
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn norm(a: Vector) -> f32 { dot(a, a).sqrt() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<K: Kinematics>(kinematics: &K, position: &[f32]) -> [f32; 4] {
+        let mut motors = [0.0; 4];
+        let mut back = [0.0; 4];
+        let axes = kinematics.cartesian_axes(motors.len());
+
+        kinematics.to_motors(position, &mut motors).unwrap();
+        kinematics.to_cartesian(&motors, &mut back[..axes]).unwrap();
+
+        for (original, actual) in position.iter().zip(&back) {
+            assert!((original - actual).abs() < 1e-2, "{:?}", back);
+        }
+
+        motors
+    }
+
+    #[test]
+    fn core_xy() {
+        let motors = round_trip(&CoreXY, &[10.0, 3.0, -5.0, 1.0]);
+        assert_eq!(motors, [13.0, 7.0, -5.0, 1.0]);
+
+        let motors = round_trip(&HBot, &[10.0, 3.0, -5.0, 1.0]);
+        assert_eq!(motors, [13.0, 7.0, -5.0, 1.0]);
+    }
+
+    #[test]
+    fn dual_gantry() {
+        let motors = round_trip(&DualGantry::new(1), &[10.0, 3.0, -5.0]);
+        assert_eq!(motors, [10.0, 3.0, -5.0, 3.0]);
+    }
+
+    #[test]
+    fn delta_carriages() {
+        let delta = LinearDelta::new(250.0, 100.0);
+
+        // in the middle, every carriage is at the same height
+        let motors = round_trip(&delta, &[0.0, 0.0, 0.0, 7.0]);
+        let height = (250.0_f32 * 250.0 - 100.0 * 100.0).sqrt();
+        for &motor in &motors[..3] {
+            assert!((motor - height).abs() < 1e-2, "{:?}", motors);
+        }
+        assert_eq!(motors[3], 7.0);
+
+        // moving towards the back tower raises its carriage
+        let motors = round_trip(&delta, &[20.0, 50.0, -30.0, 0.0]);
+        assert!(motors[2] > motors[0] && motors[2] > motors[1]);
+    }
+
+    #[test]
+    fn too_few_motors_are_unreachable() {
+        assert_eq!(DualGantry::new(0).cartesian_axes(0), 0);
+        assert_eq!(
+            DualGantry::new(0).to_motors(&[], &mut []),
+            Err(Unreachable)
+        );
+        assert_eq!(
+            DualGantry::new(0).to_cartesian(&[], &mut []),
+            Err(Unreachable)
+        );
+        // the doubled axis doesn't exist
+        assert_eq!(
+            DualGantry::new(2).to_motors(&[1.0, 2.0], &mut [0.0; 3]),
+            Err(Unreachable)
+        );
+
+        assert_eq!(CoreXY.to_motors(&[1.0], &mut [0.0]), Err(Unreachable));
+        assert_eq!(CoreXY.to_cartesian(&[1.0], &mut [0.0]), Err(Unreachable));
+
+        let delta = LinearDelta::new(250.0, 100.0);
+        assert_eq!(
+            delta.to_motors(&[0.0, 0.0], &mut [0.0; 2]),
+            Err(Unreachable)
+        );
+        assert_eq!(
+            delta.to_cartesian(&[200.0, 200.0], &mut [0.0; 2]),
+            Err(Unreachable)
+        );
+
+        // a machine without enough motors rejects Cartesian moves instead
+        // of panicking
+        let mut machine =
+            crate::MultiDriver::with_kinematics(DualGantry::new(0));
+        assert_eq!(machine.enqueue_cartesian(&[]), Err(MoveError::Unreachable));
+    }
+
+    #[test]
+    fn delta_reach() {
+        let delta = LinearDelta::new(250.0, 100.0);
+        let mut motors = [0.0; 3];
+
+        assert_eq!(
+            delta.to_motors(&[400.0, 0.0, 0.0], &mut motors),
+            Err(Unreachable)
+        );
+    }
+}
@@ -5,11 +5,10 @@
 //! 
 //! The most common way of using this crate is by driving an axis to a
 //! particular location.
-//! 
-//! ```rust
-//! use accel_stepper::{Driver, SystemClock};
-//! # use core::time::Duration;
-//! # use core::cell::RefCell;
+#![cfg_attr(feature = "manual-clock", doc = "```rust")]
+#![cfg_attr(not(feature = "manual-clock"), doc = "```rust,ignore")]
+//! use accel_stepper::{Driver, ManualClock};
+//! use core::time::Duration;
 //! 
 //! let mut axis = Driver::new();
 //! // Make sure to set your device's motion parameters
@@ -18,20 +17,10 @@
 //! 
 //! // The axis needs a clock for timing purposes. This could be an
 //! // `accel_stepper::OperatingSystemClock` when compiled with the `std`
-//! // feature, or your device's external oscillator
-//! 
-//! #[derive(Debug, Default)]
-//! struct TickingClock(RefCell<Duration>);
-//! 
-//! impl SystemClock for TickingClock {
-//!     fn elapsed(&self) -> Duration {
-//!         let mut ticks = self.0.borrow_mut();
-//!         *ticks = *ticks + Duration::from_millis(10);
-//!         ticks.clone()
-//!     }
-//! }
-//! 
-//! let clock = TickingClock::default();
+//! // feature, or your device's external oscillator. Here we use a
+//! // `ManualClock` (from the `manual-clock` feature) which moves forward by
+//! // 10ms every time it is read.
+//! let clock = ManualClock::with_auto_advance(Duration::from_millis(10));
 //! 
 //! let mut forward = 0;
 //! let mut back = 0;
@@ -58,7 +47,7 @@
 //! assert_eq!(0, back);
 //! # Result::<(), Box<dyn std::error::Error>>::Ok(())
 //! ```
-//!
+//! 
 //! # Cargo Features
 //!
 //! To minimise compile time and code size, this crate uses cargo features.
@@ -67,9 +56,22 @@
 //!   the OS clock)
 //! - `hal` - Enable functionality which implements [`Device`] on top of traits
 //!   from the [`embedded-hal`][hal] crate.
+//! - `embedded-hal-1` - The same set of devices, implemented on top of
+//!   `embedded-hal` 1.0 (see the [`hal1`] module).
+//! - `fugit` - Use a timer from the [`fugit`][fugit] crate as a [`SystemClock`]
+//!   (see [`WrappingClock::from_fugit()`]).
+//! - `embedded-time` - Use a timer from the [`embedded-time`][embedded-time]
+//!   crate as a [`SystemClock`] (see [`WrappingClock::from_embedded_time()`]).
+//! - `rtic` - Step a [`Driver`] from tasks scheduled on an RTIC monotonic timer
+//!   (see the [`rtic`] module).
+//! - `manual-clock` - A [`ManualClock`] for writing deterministic tests and
+//!   simulations.
+//! - `gcode` - Drive a [`MultiDriver`] using G-code (see the [`gcode`] module).
 //!
 //! [original]: http://www.airspayce.com/mikem/arduino/AccelStepper/index.html
 //! [hal]: https://crates.io/crates/embedded-hal
+//! [fugit]: https://crates.io/crates/fugit
+//! [embedded-time]: https://crates.io/crates/embedded-time
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -77,24 +79,55 @@
 #[macro_use]
 extern crate std;
 
+mod arc;
 mod clock;
 mod device;
 mod driver;
+#[cfg(feature = "gcode")]
+pub mod gcode;
+#[cfg(feature = "embedded-hal-1")]
+pub mod hal1;
+#[cfg(any(feature = "hal", feature = "embedded-hal-1"))]
+mod hal_common;
 #[cfg(feature = "hal")]
 mod hal_devices;
+mod homing;
+mod integer_driver;
+mod kinematics;
 mod multi_driver;
+mod planner;
+mod profile;
+#[cfg(feature = "rtic")]
+pub mod rtic;
+#[cfg(test)]
+mod test_support;
 mod utils;
 
 pub use crate::{
-    clock::SystemClock,
+    arc::{ArcCentre, ArcDirection, ArcError, Plane},
+    clock::{SystemClock, Ticks, WrappingClock},
     device::{fallible_func_device, func_device, Device, StepContext},
-    driver::Driver,
+    driver::{Driver, RunOutcome},
+    homing::{
+        func_limit_switch, Homing, HomingDirection, HomingError, HomingState,
+        LimitSwitch,
+    },
+    integer_driver::IntegerDriver,
+    kinematics::{
+        Cartesian, CoreXY, DualGantry, HBot, Kinematics, LinearDelta,
+        MoveError, Unreachable,
+    },
     multi_driver::MultiDriver,
+    planner::{QueueFull, Segment},
+    profile::MotionProfile,
     utils::CummulativeSteps,
 };
 
 #[cfg(feature = "std")]
 pub use crate::clock::OperatingSystemClock;
 
+#[cfg(any(feature = "manual-clock", test))]
+pub use crate::clock::ManualClock;
+
 #[cfg(feature = "hal")]
 pub use crate::hal_devices::*;
@@ -1,14 +1,252 @@
-use crate::{utils::DurationHelpers, Device, Driver, SystemClock};
+use crate::{
+    arc::ArcMove, driver::run_until_stopped, planner::Planner,
+    utils::Stopwatch, ArcCentre, ArcDirection, ArcError, Cartesian, Device,
+    Driver, Kinematics, MotionProfile, MoveError, Plane, QueueFull, RunOutcome,
+    Segment, SystemClock, Unreachable,
+};
 #[allow(unused_imports)]
 use arrayvec::ArrayVec;
 use core::time::Duration;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use libm::F32Ext;
+
+#[cfg(feature = "std")]
+pub(crate) type Axes<T> = Vec<T>;
+#[cfg(not(feature = "std"))]
+pub(crate) type Axes<T> = ArrayVec<T, { MultiDriver::MAX_DRIVERS }>;
+
+/// Copy a slice of per-axis values so they can be stored.
+pub(crate) fn copy_axes<T: Clone>(values: &[T]) -> Axes<T> {
+    // Note: to_vec() isn't available without std
+    #[allow(clippy::iter_cloned_collect)]
+    values.iter().cloned().collect()
+}
 
 /// Controller for moving multiple axes in a coordinated fashion.
-pub struct MultiDriver {
-    #[cfg(feature = "std")]
-    drivers: Vec<Driver>,
-    #[cfg(not(feature = "std"))]
-    drivers: ArrayVec<[Driver; MultiDriver::MAX_DRIVERS]>,
+///
+/// Moves started with [`MultiDriver::move_to()`] are straight lines, with
+/// every axis following the same acceleration-limited profile scaled by the
+/// distance it needs to travel. That way all axes start and stop together,
+/// and intermediate positions always lie on the line between the start and
+/// end points.
+///
+/// Circular and helical moves can be made with [`MultiDriver::arc_to()`].
+///
+/// Alternatively, straight-line moves can be added to a queue with
+/// [`MultiDriver::enqueue()`]. A look-ahead planner then works out how fast
+/// we can go through each corner, so a path made of many short segments
+/// doesn't need to stop at the end of each one. The queue holds up to `N`
+/// segments and is stored inline, so a longer queue gives the planner more
+/// room to look ahead at the cost of RAM. Use
+/// [`MultiDriver::with_queue_capacity()`] to pick a different size.
+///
+/// Positions passed to these methods are motor positions. Machines where
+/// motors don't map one-to-one to axes (e.g. a CoreXY gantry or a delta) can
+/// provide their [`Kinematics`] with [`MultiDriver::with_kinematics()`], then
+/// use [`MultiDriver::move_to_cartesian()`] and
+/// [`MultiDriver::enqueue_cartesian()`] to move in Cartesian coordinates.
+pub struct MultiDriver<K = Cartesian, const N: usize = 4> {
+    drivers: Axes<Driver>,
+    kinematics: K,
+    current_move: Option<CoordinatedMove>,
+    arc_tolerance: f32,
+    max_path_speed: Option<f32>,
+    planner: Planner<N>,
+    /// The queued segment currently being executed.
+    current_segment: Option<Segment>,
+    /// Set when segments have been queued since the current segment's exit
+    /// speed was last calculated.
+    needs_replan: bool,
+    /// A Cartesian line which is still being added to the queue.
+    cartesian_line: Option<CartesianLine>,
+}
+
+enum CoordinatedMove {
+    Linear(LinearMove),
+    Arc(ArcMove),
+}
+
+/// The straight-line move currently being executed.
+///
+/// The axis with the furthest to go (the "dominant" axis) is stepped according
+/// to `profile`, and every other axis uses Bresenham's line algorithm to
+/// decide whether it should also step at the same time. This uses integer
+/// arithmetic so there's no rounding drift between axes, no matter how long
+/// the move is.
+struct LinearMove {
+    /// How far each axis needs to travel.
+    deltas: Axes<i64>,
+    /// The Bresenham error term for each axis.
+    errors: Axes<i64>,
+    /// The number of steps taken by the dominant axis.
+    length: i64,
+    /// How many times the dominant axis has stepped so far.
+    steps_taken: i64,
+    /// The profile followed by the dominant axis.
+    profile: MotionProfile,
+    /// The fastest the dominant axis is allowed to go.
+    max_speed: f32,
+    /// How far the dominant axis had already travelled when `profile` was
+    /// started.
+    distance_offset: f32,
+    /// Started by the first [`MultiDriver::poll()`] of this move.
+    stopwatch: Stopwatch,
+}
+
+impl LinearMove {
+    fn new(
+        deltas: Axes<i64>,
+        profile: MotionProfile,
+        max_speed: f32,
+    ) -> LinearMove {
+        let length = dominant_distance(&deltas);
+
+        LinearMove {
+            // starting half way means we step as close as possible to the
+            // ideal line instead of always lagging behind it
+            errors: deltas.iter().map(|_| length / 2).collect(),
+            deltas,
+            length,
+            steps_taken: 0,
+            profile,
+            max_speed,
+            distance_offset: 0.0,
+            stopwatch: Stopwatch::new(),
+        }
+    }
+
+    /// Create a move which follows a queued [`Segment`], converting its
+    /// speeds from distance along the path to dominant axis steps.
+    fn for_segment(segment: &Segment) -> LinearMove {
+        let deltas = copy_axes(segment.deltas());
+        let length = dominant_distance(&deltas) as f32;
+        let scale = length / segment.length();
+
+        let max_speed = segment.max_speed() * scale;
+        let profile = MotionProfile::between(
+            length,
+            segment.entry_speed() * scale,
+            segment.exit_speed() * scale,
+            max_speed,
+            segment.acceleration() * scale,
+        );
+
+        LinearMove::new(deltas, profile, max_speed)
+    }
+
+    /// How far the dominant axis should have travelled by now, and how fast
+    /// it should be going.
+    fn progress(&mut self, now: Duration) -> (f32, f32) {
+        let elapsed = self.stopwatch.elapsed(now);
+
+        (
+            self.distance_offset + self.profile.distance_at(elapsed),
+            self.profile.speed_at(elapsed),
+        )
+    }
+
+    /// Switch to a new profile for the rest of the move, starting from now.
+    fn retime(&mut self, now: Duration, profile: MotionProfile) {
+        let (distance, _) = self.progress(now);
+
+        self.distance_offset = distance;
+        self.profile = profile;
+        self.stopwatch = Stopwatch::started(now, Duration::new(0, 0));
+    }
+
+    /// Cover the rest of the move at the maximum speed, without accelerating
+    /// or decelerating.
+    fn run_at_constant_speed(&mut self, now: Duration) {
+        let (distance, _) = self.progress(now);
+        let remaining = (self.length as f32 - distance).max(0.0);
+
+        self.retime(
+            now,
+            MotionProfile::constant_speed(remaining, self.max_speed),
+        );
+    }
+
+    /// If the profile has been completed by now, how long ago did it finish?
+    fn time_since_profile_finished(
+        &mut self,
+        now: Duration,
+    ) -> Option<Duration> {
+        self.stopwatch
+            .elapsed(now)
+            .checked_sub(self.profile.total_time())
+    }
+
+    /// Should the dominant axis take another step by this time?
+    fn step_is_due(&mut self, now: Duration) -> bool {
+        let expected = self.progress(now).0.round();
+
+        self.steps_taken < self.length && (self.steps_taken as f32) < expected
+    }
+
+    /// Advance the dominant axis by one step, calling `step_axis` with the
+    /// index and direction of every axis that needs to step.
+    fn advance<F, E>(&mut self, mut step_axis: F) -> Result<(), E>
+    where
+        F: FnMut(usize, bool) -> Result<(), E>,
+    {
+        for (i, (delta, error)) in
+            self.deltas.iter().zip(self.errors.iter_mut()).enumerate()
+        {
+            *error -= delta.abs();
+
+            if *error < 0 {
+                step_axis(i, *delta > 0)?;
+                *error += self.length;
+            }
+        }
+
+        self.steps_taken += 1;
+        Ok(())
+    }
+
+    fn is_finished(&self) -> bool { self.steps_taken >= self.length }
+}
+
+/// The distance travelled by the axis with the furthest to go.
+fn dominant_distance(deltas: &[i64]) -> i64 {
+    deltas.iter().map(|d| d.abs()).max().unwrap_or(0)
+}
+
+/// A straight line in Cartesian space, split into pieces which are added to
+/// the queue as room becomes available.
+struct CartesianLine {
+    start: Axes<f32>,
+    end: Axes<f32>,
+    pieces: u32,
+    /// The index of the next piece to queue, starting from `1`.
+    next_piece: u32,
+    max_path_speed: Option<f32>,
+}
+
+impl CartesianLine {
+    /// The point at the end of a particular piece.
+    fn point(&self, piece: u32) -> Axes<f32> {
+        let fraction = piece as f32 / self.pieces as f32;
+
+        self.start
+            .iter()
+            .zip(self.end.iter())
+            .map(|(s, e)| s + (e - s) * fraction)
+            .collect()
+    }
+
+    fn piece_length(&self) -> f32 {
+        euclidean_distance(&self.start, &self.end) / self.pieces as f32
+    }
+}
+
+pub(crate) fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (b - a) * (b - a))
+        .sum::<f32>()
+        .sqrt()
 }
 
 impl MultiDriver {
@@ -16,12 +254,46 @@ impl MultiDriver {
     /// compiled without the `std` feature.
     pub const MAX_DRIVERS: usize = 10;
 
-    pub fn new() -> MultiDriver {
+    pub fn new() -> MultiDriver { MultiDriver::with_kinematics(Cartesian) }
+}
+
+impl<K: Kinematics> MultiDriver<K> {
+    /// Create a new [`MultiDriver`] for a machine with the provided
+    /// [`Kinematics`].
+    pub fn with_kinematics(kinematics: K) -> MultiDriver<K> {
+        MultiDriver::with_queue_capacity(kinematics)
+    }
+}
+
+impl<K: Kinematics, const N: usize> MultiDriver<K, N> {
+    /// The maximum number of [`Segment`]s which can be waiting in the queue.
+    pub const QUEUE_CAPACITY: usize = N;
+
+    /// Create a new [`MultiDriver`] whose queue can hold `N` segments.
+    ///
+    /// ```rust
+    /// use accel_stepper::{Cartesian, MultiDriver};
+    ///
+    /// let multi = MultiDriver::<Cartesian, 16>::with_queue_capacity(Cartesian);
+    /// assert_eq!(multi.free_slots(), 16);
+    /// ```
+    pub fn with_queue_capacity(kinematics: K) -> MultiDriver<K, N> {
         MultiDriver {
             drivers: Default::default(),
+            kinematics,
+            current_move: None,
+            arc_tolerance: 0.5,
+            max_path_speed: None,
+            planner: Planner::new(),
+            current_segment: None,
+            needs_replan: false,
+            cartesian_line: None,
         }
     }
 
+    /// The machine's [`Kinematics`].
+    pub fn kinematics(&self) -> &K { &self.kinematics }
+
     /// Add a new [`Driver`] to the list of synchronised axes managed by the
     /// [`MultiDriver`].
     ///
@@ -33,9 +305,24 @@ impl MultiDriver {
 
     pub fn drivers(&self) -> &[Driver] { &self.drivers }
 
-    pub fn drivers_mut(&mut self) -> &mut [Driver] { &mut self.drivers }
+    /// Get mutable access to the underlying [`Driver`]s.
+    ///
+    /// This cancels any coordinated moves (including everything in the
+    /// queue), so each [`Driver`] will be polled independently from then on.
+    pub fn drivers_mut(&mut self) -> &mut [Driver] {
+        self.cancel_coordinated_moves();
+        &mut self.drivers
+    }
 
-    /// Set the target positions of all managed steppers.
+    /// Start a coordinated straight-line move to the provided positions.
+    ///
+    /// The axis with the furthest to travel follows a trapezoidal profile, and
+    /// every other axis moves proportionally so they all arrive at the same
+    /// time. Each axis's maximum speed and acceleration are respected, so the
+    /// move is only as fast as its most constrained axis allows.
+    ///
+    /// The steppers are assumed to be stationary when a move is started, and
+    /// any queued moves are discarded.
     ///
     /// # Panics
     ///
@@ -44,39 +331,438 @@ impl MultiDriver {
     pub fn move_to(&mut self, positions: &[i64]) {
         assert_eq!(positions.len(), self.drivers.len());
 
-        if self.drivers.is_empty() {
-            return;
-        }
+        self.start_linear_move(positions, self.max_path_speed);
+    }
+
+    fn start_linear_move(
+        &mut self,
+        positions: &[i64],
+        max_path_speed: Option<f32>,
+    ) {
+        self.cancel_coordinated_moves();
 
-        // first find the stepper that will take the longest time to move
-        let longest_time = self
+        let deltas: Axes<i64> = self
             .drivers
             .iter()
             .zip(positions)
-            .map(|(d, p)| time_to_move(d, *p))
-            .max()
-            .expect("There is always a least one time");
+            .map(|(d, p)| p - d.current_position())
+            .collect();
+        let length = dominant_distance(&deltas) as f32;
 
-        if longest_time == Duration::new(0, 0) {
+        if length == 0.0 {
             // nothing else needs to be done
             return;
         }
 
-        let longest_time = longest_time.as_secs_f32_2();
+        // Scale the limits for each axis up to the longest axis, then take
+        // whichever is the most restrictive
+        let mut max_speed = f32::INFINITY;
+        let mut acceleration = f32::INFINITY;
 
-        // Now work out a new max speed for each stepper so they will all
-        // arrived at the same time of longestTime
-        for (i, driver) in self.drivers.iter_mut().enumerate() {
-            let distance = positions[i] - driver.current_position();
+        for (driver, position) in self.drivers.iter().zip(positions) {
+            let distance = (position - driver.current_position()).abs() as f32;
 
-            driver.move_to(positions[i]);
-            driver.set_speed(distance as f32 / longest_time);
+            if distance > 0.0 {
+                let scale = length / distance;
+                max_speed = max_speed.min(driver.max_speed() * scale);
+                acceleration = acceleration.min(driver.acceleration() * scale);
+            }
         }
+
+        if let Some(path_speed) = max_path_speed {
+            let path_length =
+                deltas.iter().map(|&d| (d * d) as f32).sum::<f32>().sqrt();
+            max_speed = max_speed.min(path_speed * length / path_length);
+        }
+
+        let linear_move = LinearMove::new(
+            deltas,
+            MotionProfile::new(length, 0.0, max_speed, acceleration),
+            max_speed,
+        );
+
+        for (driver, position) in self.drivers.iter_mut().zip(positions) {
+            driver.set_externally_driven_target(*position);
+        }
+
+        self.current_move = Some(CoordinatedMove::Linear(linear_move));
+    }
+
+    /// Start a circular move in the provided [`Plane`], finishing at `end`.
+    ///
+    /// Any axes outside the plane move linearly at the same time, so moving
+    /// the plane's perpendicular axis traces out a helix. All coordinates are
+    /// measured in steps, so the plane's two axes should have the same number
+    /// of steps per unit of distance for the arc to be circular.
+    ///
+    /// The arc is approximated by chords which never stray further than
+    /// [`MultiDriver::arc_tolerance()`] from the true arc. A single
+    /// acceleration-limited profile is used for the entire path, limited so
+    /// each axis stays within its maximum speed and acceleration (including
+    /// the centripetal acceleration needed to go around the curve).
+    ///
+    /// The steppers are assumed to be stationary when a move is started, and
+    /// any queued moves are discarded.
+    ///
+    /// # Panics
+    ///
+    /// The number of managed steppers should be the same as the number of
+    /// positions, and there must be enough steppers for the [`Plane`]'s axes.
+    pub fn arc_to(
+        &mut self,
+        end: &[i64],
+        centre: ArcCentre,
+        direction: ArcDirection,
+        plane: Plane,
+    ) -> Result<(), ArcError> {
+        assert_eq!(end.len(), self.drivers.len());
+
+        let arc = ArcMove::new(
+            &self.drivers,
+            end,
+            centre,
+            direction,
+            plane,
+            self.arc_tolerance,
+            self.max_path_speed,
+        )?;
+
+        self.cancel_coordinated_moves();
+
+        for (driver, position) in self.drivers.iter_mut().zip(end) {
+            driver.set_externally_driven_target(*position);
+        }
+
+        self.current_move = Some(CoordinatedMove::Arc(arc));
+        Ok(())
+    }
+
+    /// Set the maximum distance (in steps) the chords used by
+    /// [`MultiDriver::arc_to()`] may deviate from the true arc.
+    pub fn set_arc_tolerance(&mut self, tolerance: f32) {
+        debug_assert!(tolerance > 0.0);
+        self.arc_tolerance = tolerance;
+    }
+
+    /// Get the arc tolerance.
+    pub fn arc_tolerance(&self) -> f32 { self.arc_tolerance }
+
+    /// Limit how fast (in `steps/second`) we may travel along the path for
+    /// any moves started or queued from now on, or `None` to only be limited
+    /// by each axis's maximum speed.
+    ///
+    /// This is typically used to implement a feed rate.
+    pub fn set_max_path_speed(&mut self, speed: Option<f32>) {
+        debug_assert!(speed.map(|s| s > 0.0).unwrap_or(true));
+        self.max_path_speed = speed;
+    }
+
+    /// Get the maximum path speed.
+    pub fn max_path_speed(&self) -> Option<f32> { self.max_path_speed }
+
+    /// Add a straight-line move to the end of the queue.
+    ///
+    /// The move starts wherever the previously queued move finishes, and
+    /// will be executed once everything before it is done. Speed is carried
+    /// through the corners between segments where possible (see
+    /// [`MultiDriver::set_junction_deviation()`]), while always leaving enough
+    /// room to stop by the end of the queue.
+    ///
+    /// # Panics
+    ///
+    /// The number of managed steppers should be the same as the number of
+    /// positions.
+    pub fn enqueue(&mut self, positions: &[i64]) -> Result<(), QueueFull> {
+        assert_eq!(positions.len(), self.drivers.len());
+
+        if self.cartesian_line.is_some() {
+            // the queue needs to finish accepting the previous line first
+            return Err(QueueFull);
+        }
+
+        let max_path_speed = self.max_path_speed;
+        self.push_segment(positions, |_| max_path_speed)
+    }
+
+    /// Add a segment to the end of the queue, where its maximum speed is
+    /// calculated from the distance each motor travels.
+    fn push_segment<F>(
+        &mut self,
+        positions: &[i64],
+        max_path_speed: F,
+    ) -> Result<(), QueueFull>
+    where
+        F: FnOnce(&[i64]) -> Option<f32>,
+    {
+        let start: Axes<i64> = match self.planner.last_target() {
+            Some(target) => copy_axes(target),
+            None => self.drivers.iter().map(|d| d.target_position()).collect(),
+        };
+
+        self.planner.push(
+            &self.drivers,
+            &start,
+            positions,
+            max_path_speed(&start),
+            self.current_segment.as_ref(),
+        )?;
+        self.needs_replan = true;
+
+        Ok(())
+    }
+
+    /// Start a coordinated move to a position in Cartesian coordinates (see
+    /// [`Kinematics`]).
+    ///
+    /// Each motor moves to its final position in a straight line like
+    /// [`MultiDriver::move_to()`], so for kinematics where straight lines
+    /// aren't preserved (e.g. a [`crate::LinearDelta`]) the path in between
+    /// won't be straight. Use [`MultiDriver::enqueue_cartesian()`] when the
+    /// path matters.
+    ///
+    /// The [`MultiDriver::max_path_speed()`] is measured along the Cartesian
+    /// path.
+    ///
+    /// # Panics
+    ///
+    /// The number of positions should be the same as the number of Cartesian
+    /// axes.
+    pub fn move_to_cartesian(
+        &mut self,
+        position: &[f32],
+    ) -> Result<(), Unreachable> {
+        let start = self.positions_to_cartesian(
+            self.drivers.iter().map(|d| d.current_position()),
+        )?;
+        let targets = self.cartesian_to_motors(position)?;
+        let max_path_speed = self.max_path_speed.map(|speed| {
+            let motors: Axes<f32> = self
+                .drivers
+                .iter()
+                .map(|d| d.current_position() as f32)
+                .collect();
+            let targets: Axes<f32> =
+                targets.iter().map(|&t| t as f32).collect();
+            speed * euclidean_distance(&motors, &targets)
+                / euclidean_distance(&start, position)
+        });
+
+        self.start_linear_move(&targets, max_path_speed);
+        Ok(())
+    }
+
+    /// Add a straight line in Cartesian coordinates (see [`Kinematics`]) to
+    /// the end of the queue.
+    ///
+    /// If the [`Kinematics`] don't preserve straight lines, the line is split
+    /// into pieces no longer than [`Kinematics::max_segment_length()`]. Pieces
+    /// are added to the queue as room becomes available, and any further
+    /// moves will be rejected with [`MoveError::QueueFull`] until every piece
+    /// has been queued.
+    ///
+    /// The [`MultiDriver::max_path_speed()`] is measured along the Cartesian
+    /// path.
+    ///
+    /// # Panics
+    ///
+    /// The number of positions should be the same as the number of Cartesian
+    /// axes.
+    pub fn enqueue_cartesian(
+        &mut self,
+        position: &[f32],
+    ) -> Result<(), MoveError> {
+        assert_eq!(
+            position.len(),
+            self.kinematics.cartesian_axes(self.drivers.len())
+        );
+
+        if self.cartesian_line.is_some() || self.planner.free_slots() == 0 {
+            return Err(MoveError::QueueFull);
+        }
+
+        let start = match self.planner.last_target() {
+            Some(target) => self.positions_to_cartesian(target.iter().cloned()),
+            None => self.positions_to_cartesian(
+                self.drivers.iter().map(|d| d.target_position()),
+            ),
+        }?;
+
+        self.cartesian_line = Some(self.split_cartesian_line(start, position)?);
+        self.queue_cartesian_line();
+
+        Ok(())
+    }
+
+    /// Check that [`MultiDriver::enqueue_cartesian()`] will be able to add a
+    /// line between two Cartesian positions once there is room for it.
+    #[cfg(feature = "gcode")]
+    pub(crate) fn check_cartesian_line(
+        &self,
+        start: &[f32],
+        end: &[f32],
+    ) -> Result<(), Unreachable> {
+        self.split_cartesian_line(copy_axes(start), end).map(|_| ())
+    }
+
+    /// Split a Cartesian line into pieces the [`Kinematics`] can follow,
+    /// making sure the entire line is reachable.
+    fn split_cartesian_line(
+        &self,
+        start: Axes<f32>,
+        end: &[f32],
+    ) -> Result<CartesianLine, Unreachable> {
+        let length = euclidean_distance(&start, end);
+        let pieces = match self.kinematics.max_segment_length() {
+            Some(max_length) if max_length > 0.0 => {
+                (length / max_length).ceil().max(1.0) as u32
+            },
+            _ => 1,
+        };
+        let line = CartesianLine {
+            start,
+            end: copy_axes(end),
+            pieces,
+            next_piece: 1,
+            max_path_speed: self.max_path_speed,
+        };
+
+        for piece in 1..=pieces {
+            self.cartesian_to_motors(&line.point(piece))?;
+        }
+
+        Ok(line)
+    }
+
+    /// Find the current Cartesian position, writing it to `position`.
+    ///
+    /// # Panics
+    ///
+    /// The `position` buffer should have the same length as the number of
+    /// Cartesian axes.
+    pub fn cartesian_position(
+        &self,
+        position: &mut [f32],
+    ) -> Result<(), Unreachable> {
+        let current = self.positions_to_cartesian(
+            self.drivers.iter().map(|d| d.current_position()),
+        )?;
+        position.copy_from_slice(&current);
+
+        Ok(())
+    }
+
+    fn positions_to_cartesian<I>(
+        &self,
+        motors: I,
+    ) -> Result<Axes<f32>, Unreachable>
+    where
+        I: IntoIterator<Item = i64>,
+    {
+        let motors: Axes<f32> = motors.into_iter().map(|m| m as f32).collect();
+        let mut position: Axes<f32> =
+            (0..self.kinematics.cartesian_axes(motors.len()))
+                .map(|_| 0.0)
+                .collect();
+
+        self.kinematics.to_cartesian(&motors, &mut position)?;
+        Ok(position)
+    }
+
+    fn cartesian_to_motors(
+        &self,
+        position: &[f32],
+    ) -> Result<Axes<i64>, Unreachable> {
+        let mut motors: Axes<f32> = self.drivers.iter().map(|_| 0.0).collect();
+        self.kinematics.to_motors(position, &mut motors)?;
+
+        Ok(motors.iter().map(|m| m.round() as i64).collect())
+    }
+
+    /// Add as much of the pending Cartesian line to the queue as will fit.
+    fn queue_cartesian_line(&mut self) {
+        let mut line = match self.cartesian_line.take() {
+            Some(line) => line,
+            None => return,
+        };
+        let piece_length = line.piece_length();
+
+        while line.next_piece <= line.pieces && self.planner.free_slots() > 0 {
+            let targets = self
+                .cartesian_to_motors(&line.point(line.next_piece))
+                .expect("We already checked the line is reachable");
+
+            self.push_segment(&targets, |start| {
+                // convert the Cartesian speed to a speed for the motors
+                let motor_length = start
+                    .iter()
+                    .zip(targets.iter())
+                    .map(|(s, t)| ((t - s) * (t - s)) as f32)
+                    .sum::<f32>()
+                    .sqrt();
+
+                match line.max_path_speed {
+                    Some(speed) if piece_length > 0.0 => {
+                        Some(speed * motor_length / piece_length)
+                    },
+                    _ => None,
+                }
+            })
+            .expect("We already checked there is room");
+
+            line.next_piece += 1;
+        }
+
+        if line.next_piece <= line.pieces {
+            self.cartesian_line = Some(line);
+        }
+    }
+
+    /// Discard as much of the queue as possible.
+    ///
+    /// If we are part way through a segment which was planned to finish at
+    /// speed, the segments needed to slow down without exceeding the
+    /// acceleration limits are kept.
+    pub fn flush(&mut self) {
+        let exit_speed = self
+            .current_segment
+            .as_ref()
+            .map(|s| s.exit_speed())
+            .unwrap_or(0.0);
+
+        self.planner.flush(exit_speed);
+        self.needs_replan = false;
+        self.cartesian_line = None;
+    }
+
+    /// How many more moves can be added to the queue.
+    pub fn free_slots(&self) -> usize { self.planner.free_slots() }
+
+    /// The queued [`Segment`] currently being executed, if any.
+    pub fn current_segment(&self) -> Option<&Segment> {
+        self.current_segment.as_ref()
+    }
+
+    /// Set how far (in steps) the path may deviate from the corner between
+    /// two queued moves, which determines how fast we can go through it.
+    ///
+    /// Larger values give faster cornering, while `0` will come to a complete
+    /// stop at every corner.
+    pub fn set_junction_deviation(&mut self, deviation: f32) {
+        debug_assert!(deviation >= 0.0);
+        self.planner.set_junction_deviation(deviation);
+    }
+
+    /// Get the junction deviation.
+    pub fn junction_deviation(&self) -> f32 {
+        self.planner.junction_deviation()
     }
 
     /// Poll the underlying [`Driver`]s, emitting steps to the provided
     /// [`Device`]s when necessary.
     ///
+    /// During a coordinated move each axis takes at most one step per poll,
+    /// so this should be called at least as often as the dominant axis steps.
+    ///
     /// # Panics
     ///
     /// The number of managed steppers should be the same as the number of
@@ -92,21 +778,826 @@ impl MultiDriver {
     {
         assert_eq!(devices.len(), self.drivers.len());
 
-        for (driver, dev) in self.drivers.iter_mut().zip(devices.iter_mut()) {
-            driver.poll(dev, clock)?;
+        if self.cartesian_line.is_some() {
+            self.queue_cartesian_line();
+        }
+
+        if self.current_move.is_none() && self.planner.is_empty() {
+            for (driver, dev) in self.drivers.iter_mut().zip(devices.iter_mut())
+            {
+                driver.poll(dev, clock)?;
+            }
+
+            return Ok(());
+        }
+
+        let now = clock.elapsed();
+
+        if self.current_move.is_none() {
+            // starting the queue from a standstill
+            self.start_next_segment(now, Duration::new(0, 0), 0.0);
+        } else if self.needs_replan {
+            self.replan_current_segment(now);
+        }
+
+        let current_move = match self.current_move {
+            Some(ref mut m) => m,
+            None => return Ok(()),
+        };
+
+        let finished = match *current_move {
+            CoordinatedMove::Linear(ref mut linear_move) => {
+                if linear_move.step_is_due(now) {
+                    let drivers = &mut self.drivers;
+
+                    linear_move.advance(|i, forward| {
+                        drivers[i].take_step(&mut devices[i], now, forward)
+                    })?;
+                }
+
+                if self.current_segment.is_some() {
+                    // queued segments run until the end of their profile so
+                    // the next segment can pick up exactly where it left off
+                    linear_move.is_finished()
+                        && linear_move
+                            .time_since_profile_finished(now)
+                            .is_some()
+                } else {
+                    linear_move.is_finished()
+                }
+            },
+            CoordinatedMove::Arc(ref mut arc) => {
+                arc.poll(&mut self.drivers, devices, now)?;
+                arc.is_finished(&self.drivers)
+            },
+        };
+
+        if finished {
+            let previous_move = self.current_move.take();
+
+            if let (
+                Some(segment),
+                Some(CoordinatedMove::Linear(mut linear_move)),
+            ) = (self.current_segment.take(), previous_move)
+            {
+                // carry straight on with the next segment
+                let overshoot = linear_move
+                    .time_since_profile_finished(now)
+                    .unwrap_or_default();
+                self.start_next_segment(now, overshoot, segment.exit_speed());
+            }
         }
 
         Ok(())
     }
 
+    fn cancel_coordinated_moves(&mut self) {
+        self.current_move = None;
+        self.current_segment = None;
+        self.planner.flush(0.0);
+        self.needs_replan = false;
+        self.cartesian_line = None;
+    }
+
+    /// Pop the next segment off the queue and start executing it, given how
+    /// long ago and how fast the previous one finished.
+    fn start_next_segment(
+        &mut self,
+        now: Duration,
+        already_elapsed: Duration,
+        initial_speed: f32,
+    ) {
+        self.planner.plan(initial_speed);
+        self.needs_replan = false;
+
+        let segment = match self.planner.pop() {
+            Some(s) => s,
+            None => return,
+        };
+
+        let mut linear_move = LinearMove::for_segment(&segment);
+        linear_move.stopwatch = Stopwatch::started(now, already_elapsed);
+
+        for (driver, position) in self.drivers.iter_mut().zip(segment.target())
+        {
+            driver.set_externally_driven_target(*position);
+        }
+
+        self.current_move = Some(CoordinatedMove::Linear(linear_move));
+        self.current_segment = Some(segment);
+    }
+
+    /// New segments have been queued, so we may be able to finish the
+    /// current segment faster than originally planned.
+    fn replan_current_segment(&mut self, now: Duration) {
+        self.needs_replan = false;
+
+        let (segment, linear_move) =
+            match (&mut self.current_segment, &mut self.current_move) {
+                (Some(s), Some(CoordinatedMove::Linear(m))) => (s, m),
+                _ => return,
+            };
+
+        let scale = linear_move.length as f32 / segment.length();
+        let (distance, speed) = linear_move.progress(now);
+        let remaining = (linear_move.length as f32 - distance).max(0.0);
+
+        // the fastest we could be going by the end of this segment
+        let reachable = ((speed / scale) * (speed / scale)
+            + 2.0 * segment.acceleration() * remaining / scale)
+            .sqrt();
+        let exit_speed = self.planner.plan(reachable);
+
+        if exit_speed > segment.exit_speed() {
+            segment.set_exit_speed(exit_speed);
+            linear_move.retime(
+                now,
+                MotionProfile::between(
+                    remaining,
+                    speed,
+                    exit_speed * scale,
+                    segment.max_speed() * scale,
+                    segment.acceleration() * scale,
+                ),
+            );
+        } else {
+            // keep the original plan
+            self.planner.plan(segment.exit_speed());
+        }
+    }
+
+    /// Block until all the managed steppers reach their target positions (see
+    /// [`Driver::run_to_position()`]).
+    ///
+    /// # Panics
+    ///
+    /// The number of managed steppers should be the same as the number of
+    /// devices.
+    pub fn run_to_position<D, C, F>(
+        &mut self,
+        devices: &mut [D],
+        clock: &C,
+        timeout: Option<Duration>,
+        should_abort: F,
+    ) -> Result<RunOutcome, D::Error>
+    where
+        D: Device,
+        C: SystemClock,
+        F: FnMut() -> bool,
+    {
+        run_until_stopped(clock, timeout, should_abort, |clock| {
+            self.poll(devices, clock)?;
+            Ok(self.is_running())
+        })
+    }
+
+    /// Block until the straight-line move started by
+    /// [`MultiDriver::move_to()`] is finished, moving at a constant speed
+    /// instead of accelerating and decelerating.
+    ///
+    /// This is the equivalent of `MultiStepper::runSpeedToPosition()`. The
+    /// dominant axis runs at the fastest speed every axis's
+    /// [`Driver::max_speed()`] (and [`MultiDriver::max_path_speed()`]) allows,
+    /// and the other axes move proportionally so they all arrive together.
+    /// Arcs and queued moves aren't affected and run with their normal
+    /// profiles. The timeout and `should_abort` callback work the same as
+    /// with [`MultiDriver::run_to_position()`].
+    ///
+    /// # Panics
+    ///
+    /// The number of managed steppers should be the same as the number of
+    /// devices.
+    pub fn run_speed_to_position<D, C, F>(
+        &mut self,
+        devices: &mut [D],
+        clock: &C,
+        timeout: Option<Duration>,
+        should_abort: F,
+    ) -> Result<RunOutcome, D::Error>
+    where
+        D: Device,
+        C: SystemClock,
+        F: FnMut() -> bool,
+    {
+        if self.current_segment.is_none() {
+            if let Some(CoordinatedMove::Linear(ref mut linear_move)) =
+                self.current_move
+            {
+                linear_move.run_at_constant_speed(clock.elapsed());
+            }
+        }
+
+        self.run_to_position(devices, clock, timeout, should_abort)
+    }
+
+    /// Move to a new set of positions and block until they are reached (see
+    /// [`MultiDriver::run_to_position()`]).
+    ///
+    /// # Panics
+    ///
+    /// The number of managed steppers should be the same as the number of
+    /// positions and devices.
+    pub fn run_to_new_position<D, C, F>(
+        &mut self,
+        positions: &[i64],
+        devices: &mut [D],
+        clock: &C,
+        timeout: Option<Duration>,
+        should_abort: F,
+    ) -> Result<RunOutcome, D::Error>
+    where
+        D: Device,
+        C: SystemClock,
+        F: FnMut() -> bool,
+    {
+        self.move_to(positions);
+        self.run_to_position(devices, clock, timeout, should_abort)
+    }
+
     /// Are any of the managed steppers still running?
     pub fn is_running(&self) -> bool {
-        self.drivers.iter().any(|d| d.is_running())
+        self.current_move.is_some()
+            || !self.planner.is_empty()
+            || self.cartesian_line.is_some()
+            || self.drivers.iter().any(|d| d.is_running())
+    }
+}
+
+impl<K: Kinematics + Default, const N: usize> Default for MultiDriver<K, N> {
+    fn default() -> MultiDriver<K, N> {
+        MultiDriver::with_queue_capacity(K::default())
     }
 }
 
-fn time_to_move(driver: &Driver, pos: i64) -> Duration {
-    let distance = driver.current_position() - pos;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{ticking_clock, Recorder},
+        ManualClock,
+    };
+    use core::f32::consts::PI;
+    use std::vec::Vec;
+
+    fn axis(max_speed: f32, acceleration: f32, position: i64) -> Driver {
+        let mut driver = Driver::new();
+        driver.set_max_speed(max_speed);
+        driver.set_acceleration(acceleration);
+        driver.set_current_position(position);
+        driver
+    }
+
+    /// Check every axis is as close as possible to the ideal line, based on
+    /// how far the dominant (first) axis has travelled.
+    fn assert_on_line(multi: &MultiDriver, origins: &[i64], targets: &[i64]) {
+        let drivers = multi.drivers();
+        let fraction = (drivers[0].current_position() - origins[0]) as f64
+            / (targets[0] - origins[0]) as f64;
+
+        for i in 1..drivers.len() {
+            let distance = (targets[i] - origins[i]) as f64;
+            let expected = origins[i] as f64 + distance * fraction;
+            let actual = drivers[i].current_position() as f64;
+            assert!(
+                (actual - expected).abs() <= 0.5 + 1e-9,
+                "axis {} is at {} but should be at {}",
+                i,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn axes_stay_on_a_straight_line() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        // the slowest axis limits the entire move
+        multi.push_driver(axis(100.0, 2000.0, 50));
+        multi.push_driver(axis(1000.0, 500.0, -20));
+        let origins = [0, 50, -20];
+        let targets = [1000, -350, 230];
+        let clock = ticking_clock();
+        let mut devices = [
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        ];
+
+        multi.move_to(&targets);
+
+        while multi.is_running() {
+            multi.poll(&mut devices, &clock).unwrap();
+
+            assert_on_line(&multi, &origins, &targets);
+        }
+
+        let positions: Vec<_> = multi
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect();
+        assert_eq!(positions, targets);
+
+        for (device, (origin, target)) in
+            devices.iter().zip(origins.iter().zip(&targets))
+        {
+            assert_eq!(device.steps.len() as i64, (target - origin).abs());
+        }
+
+        // the middle axis can only go 100 steps/sec, so it sets the pace for
+        // the others and everyone should finish together
+        let finished_at: Vec<_> = devices
+            .iter()
+            .map(|d| d.times().last().unwrap().as_secs_f32())
+            .collect();
+        let total = finished_at.iter().cloned().fold(0.0, f32::max);
+        assert!(total > 4.0);
+        for t in finished_at {
+            assert!(total - t < total * 0.02, "{} vs {}", t, total);
+        }
+    }
+
+    #[test]
+    fn no_drift_on_long_diagonal_moves() {
+        let mut multi = MultiDriver::new();
+        for _ in 0..3 {
+            multi.push_driver(axis(50_000.0, 200_000.0, 0));
+        }
+        let origins = [0, 0, 0];
+        let targets = [100_003, 33_331, -77_777];
+        let clock = ticking_clock();
+        let mut devices = [
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        ];
+
+        multi.move_to(&targets);
+
+        while multi.is_running() {
+            multi.poll(&mut devices, &clock).unwrap();
+            assert_on_line(&multi, &origins, &targets);
+        }
+
+        let positions: Vec<_> = multi
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect();
+        assert_eq!(positions, targets);
+    }
+
+    #[test]
+    fn shared_profile_respects_each_axis_limits() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(500.0, 100.0, 0));
+        multi.push_driver(axis(500.0, 1000.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+
+        multi
+            .run_to_new_position(
+                &[100, 400],
+                &mut devices,
+                &clock,
+                None,
+                || false,
+            )
+            .unwrap();
+
+        // The first axis has a quarter of the distance to travel, so the
+        // shared acceleration is limited to 4 * 100 steps/sec/sec. That
+        // means we'll take 2 * sqrt(400 / 400) = 2 seconds.
+        let finished = devices[1].times().last().unwrap().as_secs_f32();
+        assert!((finished - 2.0).abs() < 0.05, "{}", finished);
 
-    Duration::from_secs_f32_2(distance.abs() as f32 / driver.max_speed())
+        let positions: Vec<_> = multi
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect();
+        assert_eq!(positions, [100, 400]);
+        assert!(!multi.is_running());
+    }
+
+    #[test]
+    fn run_speed_to_position_moves_at_a_constant_speed() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(500.0, 100.0, 0));
+        multi.push_driver(axis(500.0, 100.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+
+        multi.move_to(&[100, 50]);
+        let outcome = multi
+            .run_speed_to_position(&mut devices, &clock, None, || false)
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::Reached);
+        let positions: Vec<_> = multi
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect();
+        assert_eq!(positions, [100, 50]);
+        assert_eq!(devices[0].steps.len(), 100);
+        assert_eq!(devices[1].steps.len(), 50);
+
+        // the dominant axis steps every 2ms from start to finish, instead of
+        // ramping up and down
+        for pair in devices[0].times().windows(2) {
+            let interval = (pair[1] - pair[0]).as_secs_f32();
+            assert!((interval - 0.002).abs() < 0.0001, "{}", interval);
+        }
+        assert!(!multi.is_running());
+    }
+
+    #[test]
+    fn moves_carry_on_when_the_clock_goes_backwards() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(1000.0, 5000.0, 0));
+        multi.push_driver(axis(1000.0, 5000.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+
+        multi.move_to(&[100, 50]);
+        while multi.drivers()[0].current_position() < 30 {
+            multi.poll(&mut devices, &clock).unwrap();
+        }
+
+        // the clock wraps around part way through the move
+        clock.set(Duration::from_millis(0));
+        let mut polls = 0;
+        while multi.drivers()[0].current_position() < 31 {
+            multi.poll(&mut devices, &clock).unwrap();
+            polls += 1;
+        }
+
+        // we're already moving, so the next step is only a few ms away
+        // instead of waiting for the clock to catch up
+        assert!(polls < 200, "{}", polls);
+
+        while multi.is_running() {
+            multi.poll(&mut devices, &clock).unwrap();
+        }
+        assert_eq!(devices[0].steps.len(), 100);
+        assert_eq!(devices[1].steps.len(), 50);
+    }
+
+    #[test]
+    fn run_speed_to_position_can_be_aborted() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(500.0, 100.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default()];
+
+        multi.move_to(&[100]);
+        let outcome = multi
+            .run_speed_to_position(&mut devices, &clock, None, || true)
+            .unwrap();
+
+        assert_eq!(outcome, RunOutcome::Aborted);
+        assert!(multi.is_running());
+    }
+
+    #[test]
+    fn helical_arcs_stay_close_to_the_true_arc() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(500.0, 2000.0, 200));
+        multi.push_driver(axis(500.0, 2000.0, 0));
+        multi.push_driver(axis(500.0, 2000.0, 0));
+        multi.set_arc_tolerance(0.25);
+        let clock = ticking_clock();
+        let mut devices = [
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        ];
+
+        // a quarter circle around the origin, climbing 50 steps as we go
+        multi
+            .arc_to(
+                &[0, 200, 50],
+                ArcCentre::Offset(-200.0, 0.0),
+                ArcDirection::CounterClockwise,
+                Plane::XY,
+            )
+            .unwrap();
+
+        while multi.is_running() {
+            multi.poll(&mut devices, &clock).unwrap();
+
+            let drivers = multi.drivers();
+            let x = drivers[0].current_position() as f32;
+            let y = drivers[1].current_position() as f32;
+            let z = drivers[2].current_position() as f32;
+
+            // the tolerance, plus rounding to the nearest step on each axis
+            let radius = x.hypot(y);
+            assert!((radius - 200.0).abs() <= 0.25 + 0.75, "{}", radius);
+
+            // the helix climbs at a constant rate as we go around
+            let angle = y.atan2(x);
+            let expected_z = 50.0 * angle / (PI / 2.0);
+            assert!((z - expected_z).abs() <= 1.5, "{} vs {}", z, expected_z);
+        }
+
+        let positions: Vec<_> = multi
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect();
+        assert_eq!(positions, [0, 200, 50]);
+        assert_eq!(devices[2].steps.len(), 50);
+
+        // the axes should never go faster than 500 steps/second
+        for device in &devices {
+            for pair in device.times().windows(2) {
+                let interval = (pair[1] - pair[0]).as_secs_f32();
+                assert!(interval >= 0.95 / 500.0, "{}", interval);
+            }
+        }
+    }
+
+    #[test]
+    fn full_circles_return_to_the_start() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(1000.0, 5000.0, 0));
+        multi.push_driver(axis(1000.0, 5000.0, 0));
+        multi.push_driver(axis(1000.0, 5000.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        ];
+
+        multi
+            .arc_to(
+                &[0, 0, 0],
+                ArcCentre::Offset(0.0, 100.0),
+                ArcDirection::Clockwise,
+                Plane::YZ,
+            )
+            .unwrap();
+        multi
+            .run_to_position(&mut devices, &clock, None, || false)
+            .unwrap();
+
+        let positions: Vec<_> = multi
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect();
+        assert_eq!(positions, [0, 0, 0]);
+        // the X axis doesn't move, while Y and Z each travel two diameters
+        assert!(devices[0].steps.is_empty());
+        assert_eq!(devices[1].steps.len(), 400);
+        assert_eq!(devices[2].steps.len(), 400);
+    }
+
+    #[test]
+    fn radius_too_small_for_the_arc() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(1000.0, 5000.0, 0));
+        multi.push_driver(axis(1000.0, 5000.0, 0));
+
+        let got = multi.arc_to(
+            &[100, 0],
+            ArcCentre::Radius(10.0),
+            ArcDirection::Clockwise,
+            Plane::XY,
+        );
+
+        assert_eq!(got, Err(ArcError::RadiusTooSmall));
+        assert!(!multi.is_running());
+    }
+
+    /// Keep polling until every queued move is done.
+    fn run_queue<K: Kinematics, const N: usize>(
+        multi: &mut MultiDriver<K, N>,
+        devices: &mut [Recorder],
+        clock: &ManualClock,
+    ) {
+        while multi.is_running() {
+            multi.poll(devices, clock).unwrap();
+        }
+    }
+
+    /// A [`MultiDriver`] with room for plenty of queued segments.
+    type LongQueue = MultiDriver<Cartesian, 16>;
+
+    #[test]
+    fn queued_segments_carry_speed_through_straight_lines() {
+        let mut multi = LongQueue::default();
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+
+        for i in 1..=10 {
+            multi.enqueue(&[i * 100, i * 50]).unwrap();
+        }
+        assert_eq!(multi.free_slots(), LongQueue::QUEUE_CAPACITY - 10);
+        run_queue(&mut multi, &mut devices, &clock);
+        assert!(multi.current_segment().is_none());
+
+        // the steps should be identical to doing it in one move
+        let mut single = MultiDriver::new();
+        single.push_driver(axis(1000.0, 2000.0, 0));
+        single.push_driver(axis(1000.0, 2000.0, 0));
+        let clock = ticking_clock();
+        let mut expected = [Recorder::default(), Recorder::default()];
+        single.move_to(&[1000, 500]);
+        run_queue(&mut single, &mut expected, &clock);
+
+        for (got, expected) in devices.iter().zip(&expected) {
+            assert_eq!(got.steps.len(), expected.steps.len());
+
+            for (got, expected) in got.times().iter().zip(&expected.times()) {
+                let difference = got.as_secs_f32() - expected.as_secs_f32();
+                assert!(difference.abs() < 1e-3, "{:?} vs {:?}", got, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn enqueue_while_moving() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+        let mut top_speed = 0.0_f32;
+
+        // only keep a handful of moves in the queue, but that's still enough
+        // room to reach full speed
+        let mut next = 1;
+        multi.enqueue(&[100, 0]).unwrap();
+
+        while multi.is_running() {
+            if next < 10 && multi.free_slots() > 0 {
+                next += 1;
+                multi.enqueue(&[next * 100, 0]).unwrap();
+            }
+
+            multi.poll(&mut devices, &clock).unwrap();
+
+            if let Some(segment) = multi.current_segment() {
+                top_speed = top_speed.max(segment.entry_speed());
+            }
+        }
+
+        assert_eq!(multi.drivers()[0].current_position(), 1000);
+        assert_eq!(devices[0].steps.len(), 1000);
+        // we got up to full speed instead of stopping after each segment
+        assert!(top_speed > 990.0, "{}", top_speed);
+
+        for pair in devices[0].times().windows(2) {
+            let interval = (pair[1] - pair[0]).as_secs_f32();
+            assert!(interval >= 0.95 / 1000.0, "{}", interval);
+        }
+    }
+
+    #[test]
+    fn slow_down_for_corners() {
+        let mut multi = MultiDriver::new();
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        multi.set_junction_deviation(2.0);
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+
+        multi.enqueue(&[1000, 0]).unwrap();
+        multi.enqueue(&[1000, 1000]).unwrap();
+        // a hairpin turn means we need to stop completely
+        multi.enqueue(&[1000, 0]).unwrap();
+
+        let mut entry_speeds = Vec::new();
+        while multi.is_running() {
+            multi.poll(&mut devices, &clock).unwrap();
+
+            if let Some(segment) = multi.current_segment() {
+                if entry_speeds.last() != Some(&segment.entry_speed()) {
+                    entry_speeds.push(segment.entry_speed());
+                }
+            }
+        }
+
+        assert_eq!(entry_speeds.len(), 3);
+        assert_eq!(entry_speeds[0], 0.0);
+        assert!(entry_speeds[1] > 50.0 && entry_speeds[1] < 200.0);
+        assert_eq!(entry_speeds[2], 0.0);
+        assert_eq!(multi.drivers()[1].current_position(), 0);
+        assert_eq!(devices[1].steps.len(), 2000);
+    }
+
+    #[test]
+    fn flush_stops_as_soon_as_possible() {
+        let mut multi = LongQueue::default();
+        multi.push_driver(axis(1000.0, 1000.0, 0));
+        multi.push_driver(axis(1000.0, 1000.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+
+        for i in 1..=LongQueue::QUEUE_CAPACITY as i64 {
+            multi.enqueue(&[i * 100, 0]).unwrap();
+        }
+        assert_eq!(multi.free_slots(), 0);
+        assert_eq!(multi.enqueue(&[0, 0]), Err(QueueFull));
+
+        // wait until we're in the middle of the 5th segment
+        while multi.drivers()[0].current_position() < 450 {
+            multi.poll(&mut devices, &clock).unwrap();
+        }
+        multi.flush();
+        run_queue(&mut multi, &mut devices, &clock);
+
+        // we're going at 1000 steps/sec, which takes 500 steps to stop
+        let position = multi.drivers()[0].current_position();
+        assert_eq!(position, 1000);
+        assert!(!multi.is_running());
+        assert_eq!(multi.free_slots(), LongQueue::QUEUE_CAPACITY);
+    }
+
+    #[test]
+    fn the_queue_capacity_is_configurable() {
+        assert_eq!(MultiDriver::new().free_slots(), 4);
+        assert_eq!(LongQueue::default().free_slots(), 16);
+    }
+
+    #[test]
+    fn core_xy_moves_in_cartesian_coordinates() {
+        let mut multi = MultiDriver::with_kinematics(crate::CoreXY);
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        multi.push_driver(axis(1000.0, 2000.0, 0));
+        let clock = ticking_clock();
+        let mut devices = [Recorder::default(), Recorder::default()];
+
+        multi.enqueue_cartesian(&[100.0, 0.0]).unwrap();
+        multi.enqueue_cartesian(&[100.0, 50.0]).unwrap();
+        run_queue(&mut multi, &mut devices, &clock);
+
+        // A = X + Y and B = X - Y
+        let motors: Vec<_> = multi
+            .drivers()
+            .iter()
+            .map(|d| d.current_position())
+            .collect();
+        assert_eq!(motors, [150, 50]);
+
+        let mut position = [0.0; 2];
+        multi.cartesian_position(&mut position).unwrap();
+        assert_eq!(position, [100.0, 50.0]);
+
+        multi.move_to_cartesian(&[0.0, 0.0]).unwrap();
+        run_queue(&mut multi, &mut devices, &clock);
+        multi.cartesian_position(&mut position).unwrap();
+        assert_eq!(position, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn delta_lines_stay_straight() {
+        let mut delta = crate::LinearDelta::new(2500.0, 1000.0);
+        delta.set_segment_length(10.0);
+        let height = (2500.0_f32 * 2500.0 - 1000.0 * 1000.0).sqrt();
+        let mut multi = MultiDriver::with_kinematics(delta);
+        for _ in 0..3 {
+            multi.push_driver(axis(2000.0, 10_000.0, height.round() as i64));
+        }
+        let clock = ticking_clock();
+        let mut devices = [
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        ];
+
+        assert_eq!(
+            multi.enqueue_cartesian(&[5000.0, 0.0, 0.0]),
+            Err(MoveError::Unreachable)
+        );
+        assert!(!multi.is_running());
+
+        // this needs more pieces than fit in the queue at once
+        multi.enqueue_cartesian(&[400.0, 300.0, 0.0]).unwrap();
+        assert_eq!(multi.free_slots(), 0);
+        assert!(multi.enqueue_cartesian(&[0.0, 0.0, 0.0]).is_err());
+
+        let mut position = [0.0; 3];
+
+        while multi.is_running() {
+            multi.poll(&mut devices, &clock).unwrap();
+
+            // the effector should stay close to the line from (0, 0, 0) to
+            // (400, 300, 0)
+            multi.cartesian_position(&mut position).unwrap();
+            let off_line = (position[0] * 0.6 - position[1] * 0.8).abs();
+            assert!(off_line < 3.0, "{:?}", position);
+            assert!(position[2].abs() < 3.0, "{:?}", position);
+        }
+
+        assert!((position[0] - 400.0).abs() < 1.0, "{:?}", position);
+        assert!((position[1] - 300.0).abs() < 1.0, "{:?}", position);
+    }
 }
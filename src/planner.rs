@@ -0,0 +1,396 @@
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use libm::F32Ext;
+
+use crate::{
+    multi_driver::{copy_axes, Axes},
+    Driver,
+};
+use arrayvec::ArrayVec;
+
+/// A straight-line move which has been added to a [`crate::MultiDriver`]'s
+/// queue with [`crate::MultiDriver::enqueue()`].
+///
+/// All distances are measured in steps along the path (i.e. the length of
+/// the line through every axis), and speeds in `steps/second` along that
+/// path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    target: Axes<i64>,
+    deltas: Axes<i64>,
+    length: f32,
+    max_speed: f32,
+    acceleration: f32,
+    /// The fastest we can go through the corner at the start of this
+    /// segment.
+    max_entry_speed: f32,
+    entry_speed: f32,
+    exit_speed: f32,
+}
+
+impl Segment {
+    /// Create a segment going from `start` to `target`, limited by the
+    /// maximum speed and acceleration of each axis.
+    fn new(drivers: &[Driver], start: &[i64], target: &[i64]) -> Segment {
+        let deltas: Axes<i64> =
+            start.iter().zip(target).map(|(s, t)| t - s).collect();
+        let length = deltas.iter().map(|&d| (d * d) as f32).sum::<f32>().sqrt();
+
+        let mut max_speed = f32::INFINITY;
+        let mut acceleration = f32::INFINITY;
+
+        for (driver, delta) in drivers.iter().zip(&deltas) {
+            if *delta != 0 {
+                let scale = length / delta.abs() as f32;
+                max_speed = max_speed.min(driver.max_speed() * scale);
+                acceleration = acceleration.min(driver.acceleration() * scale);
+            }
+        }
+
+        Segment {
+            target: copy_axes(target),
+            deltas,
+            length,
+            max_speed,
+            acceleration,
+            max_entry_speed: 0.0,
+            entry_speed: 0.0,
+            exit_speed: 0.0,
+        }
+    }
+
+    /// Where each axis will be at the end of the segment.
+    pub fn target(&self) -> &[i64] { &self.target }
+
+    /// How far each axis will move during the segment.
+    pub fn deltas(&self) -> &[i64] { &self.deltas }
+
+    /// The segment's length, in steps.
+    pub fn length(&self) -> f32 { self.length }
+
+    /// The fastest the segment may be travelled while respecting each axis's
+    /// maximum speed.
+    pub fn max_speed(&self) -> f32 { self.max_speed }
+
+    /// The acceleration along the segment.
+    pub fn acceleration(&self) -> f32 { self.acceleration }
+
+    /// The planned speed at the start of the segment.
+    pub fn entry_speed(&self) -> f32 { self.entry_speed }
+
+    /// The planned speed at the end of the segment.
+    pub fn exit_speed(&self) -> f32 { self.exit_speed }
+
+    /// Update the exit speed of a segment that is already being executed.
+    pub(crate) fn set_exit_speed(&mut self, exit_speed: f32) {
+        self.exit_speed = exit_speed;
+    }
+
+    /// The fastest we can go through the corner between two segments
+    /// without deviating from the path by more than `deviation` steps,
+    /// using the "junction deviation" method popularised by grbl.
+    fn junction_speed(
+        previous: &Segment,
+        next: &Segment,
+        deviation: f32,
+    ) -> f32 {
+        let dot: f32 = previous
+            .deltas
+            .iter()
+            .zip(&next.deltas)
+            .map(|(a, b)| (a * b) as f32)
+            .sum();
+        // the cosine of the angle between the two segments, where -1 means
+        // carrying on in a straight line
+        let cos_theta = -dot / (previous.length * next.length);
+        let max_speed = previous.max_speed.min(next.max_speed);
+
+        if cos_theta > 0.999_999 {
+            // a complete reversal, we need to stop
+            return 0.0;
+        } else if cos_theta < -0.999_999 {
+            // going straight through
+            return max_speed;
+        }
+
+        let sin_half_theta = (0.5 * (1.0 - cos_theta)).sqrt();
+        let acceleration = previous.acceleration.min(next.acceleration);
+        let speed_squared =
+            acceleration * deviation * sin_half_theta / (1.0 - sin_half_theta);
+
+        speed_squared.sqrt().min(max_speed)
+    }
+}
+
+/// Returned by [`crate::MultiDriver::enqueue()`] when there is no room left
+/// in the queue.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct QueueFull;
+
+/// A queue of pending [`Segment`]s and the look-ahead planner which decides
+/// how fast to go through each corner.
+#[derive(Debug, Clone)]
+pub(crate) struct Planner<const N: usize> {
+    queue: ArrayVec<Segment, N>,
+    junction_deviation: f32,
+}
+
+impl<const N: usize> Planner<N> {
+    pub(crate) fn new() -> Planner<N> {
+        Planner {
+            queue: ArrayVec::new(),
+            junction_deviation: 1.0,
+        }
+    }
+
+    pub(crate) fn junction_deviation(&self) -> f32 { self.junction_deviation }
+
+    pub(crate) fn set_junction_deviation(&mut self, deviation: f32) {
+        self.junction_deviation = deviation;
+    }
+
+    pub(crate) fn free_slots(&self) -> usize {
+        self.queue.capacity() - self.queue.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool { self.queue.is_empty() }
+
+    /// Where the last queued segment finishes.
+    pub(crate) fn last_target(&self) -> Option<&[i64]> {
+        self.queue.last().map(|s| s.target())
+    }
+
+    /// Add a new segment to the end of the queue. The `executing` segment is
+    /// used to find the speed through the first corner when the queue is
+    /// empty.
+    ///
+    /// Zero-length moves are ignored.
+    pub(crate) fn push(
+        &mut self,
+        drivers: &[Driver],
+        start: &[i64],
+        target: &[i64],
+        max_path_speed: Option<f32>,
+        executing: Option<&Segment>,
+    ) -> Result<(), QueueFull> {
+        if self.queue.is_full() {
+            return Err(QueueFull);
+        }
+
+        let mut segment = Segment::new(drivers, start, target);
+
+        if segment.length == 0.0 {
+            return Ok(());
+        }
+
+        if let Some(speed) = max_path_speed {
+            segment.max_speed = segment.max_speed.min(speed);
+        }
+
+        if let Some(previous) = self.queue.last().or(executing) {
+            segment.max_entry_speed = Segment::junction_speed(
+                previous,
+                &segment,
+                self.junction_deviation,
+            );
+        }
+
+        self.queue.push(segment);
+        Ok(())
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Segment> {
+        if self.queue.is_empty() {
+            None
+        } else {
+            Some(self.queue.remove(0))
+        }
+    }
+
+    /// Recalculate the entry and exit speeds for every queued segment so we
+    /// can always stop by the end of the queue.
+    ///
+    /// The currently executing segment can reach at most `max_initial_speed`
+    /// by the time it finishes, and the planned speed for the start of the
+    /// queue is returned.
+    pub(crate) fn plan(&mut self, max_initial_speed: f32) -> f32 {
+        // backwards pass - make sure we can always slow down in time
+        let mut next_entry = 0.0;
+
+        for segment in self.queue.iter_mut().rev() {
+            let reachable = (next_entry * next_entry
+                + 2.0 * segment.acceleration * segment.length)
+                .sqrt();
+            segment.entry_speed = segment.max_entry_speed.min(reachable);
+            next_entry = segment.entry_speed;
+        }
+
+        // forwards pass - make sure we can speed up in time
+        let initial_speed = match self.queue.first() {
+            Some(first) => first.entry_speed.min(max_initial_speed),
+            None => return 0.0,
+        };
+        let mut previous_exit = initial_speed;
+
+        for i in 0..self.queue.len() {
+            let segment = &mut self.queue[i];
+            segment.entry_speed = segment.entry_speed.min(previous_exit);
+
+            let reachable = (segment.entry_speed * segment.entry_speed
+                + 2.0 * segment.acceleration * segment.length)
+                .sqrt();
+            let next_entry =
+                self.queue.get(i + 1).map(|s| s.entry_speed).unwrap_or(0.0);
+            previous_exit = next_entry.min(reachable);
+            self.queue[i].exit_speed = previous_exit;
+        }
+
+        initial_speed
+    }
+
+    /// Throw away as much of the queue as possible, keeping just enough to
+    /// come to a stop from `speed` without exceeding any acceleration limits.
+    pub(crate) fn flush(&mut self, mut speed: f32) {
+        let mut keep = 0;
+
+        for segment in self.queue.iter_mut() {
+            if speed <= 0.0 {
+                break;
+            }
+
+            keep += 1;
+            segment.entry_speed = speed;
+
+            let braking = 2.0 * segment.acceleration * segment.length;
+            speed = if speed * speed <= braking {
+                0.0
+            } else {
+                (speed * speed - braking).sqrt()
+            };
+            segment.exit_speed = speed;
+        }
+
+        self.queue.truncate(keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The queue capacity used in these tests.
+    const CAPACITY: usize = 8;
+
+    fn drivers() -> [Driver; 2] {
+        let mut driver = Driver::new();
+        driver.set_max_speed(1000.0);
+        driver.set_acceleration(1000.0);
+
+        let mut other = Driver::new();
+        other.set_max_speed(1000.0);
+        other.set_acceleration(1000.0);
+
+        [driver, other]
+    }
+
+    #[test]
+    fn straight_lines_keep_their_speed() {
+        let drivers = drivers();
+        let mut planner = Planner::<CAPACITY>::new();
+
+        planner
+            .push(&drivers, &[0, 0], &[1000, 0], None, None)
+            .unwrap();
+        planner
+            .push(&drivers, &[1000, 0], &[2000, 0], None, None)
+            .unwrap();
+        planner.plan(0.0);
+
+        let first = &planner.queue[0];
+        let second = &planner.queue[1];
+        assert_eq!(first.entry_speed(), 0.0);
+        assert_eq!(first.exit_speed(), 1000.0);
+        assert_eq!(second.entry_speed(), 1000.0);
+        assert_eq!(second.exit_speed(), 0.0);
+    }
+
+    #[test]
+    fn slow_down_for_corners() {
+        let drivers = drivers();
+        let mut planner = Planner::<CAPACITY>::new();
+
+        planner
+            .push(&drivers, &[0, 0], &[1000, 0], None, None)
+            .unwrap();
+        planner
+            .push(&drivers, &[1000, 0], &[1000, 1000], None, None)
+            .unwrap();
+        // a complete reversal
+        planner
+            .push(&drivers, &[1000, 1000], &[1000, 0], None, None)
+            .unwrap();
+        planner.plan(0.0);
+
+        // v^2 = a * d * sin(theta / 2) / (1 - sin(theta / 2)) for a right
+        // angle
+        let sin = 0.5_f32.sqrt();
+        let right_angle = (1000.0 * sin / (1.0 - sin)).sqrt();
+        let corner = planner.queue[1].entry_speed();
+        assert!((corner - right_angle).abs() < 1e-2, "{}", corner);
+        assert_eq!(planner.queue[0].exit_speed(), corner);
+        assert_eq!(planner.queue[2].entry_speed(), 0.0);
+    }
+
+    #[test]
+    fn short_segments_limit_the_entry_speed() {
+        let drivers = drivers();
+        let mut planner = Planner::<CAPACITY>::new();
+
+        planner
+            .push(&drivers, &[0, 0], &[1000, 0], None, None)
+            .unwrap();
+        planner
+            .push(&drivers, &[1000, 0], &[1010, 0], None, None)
+            .unwrap();
+        planner.plan(0.0);
+
+        // we need to be able to stop within the last 10 steps
+        let expected = (2.0_f32 * 1000.0 * 10.0).sqrt();
+        assert!((planner.queue[1].entry_speed() - expected).abs() < 1e-2);
+    }
+
+    #[test]
+    fn flushing_keeps_enough_room_to_stop() {
+        let drivers = drivers();
+        let mut planner = Planner::<CAPACITY>::new();
+
+        for i in 0..5 {
+            let start = [i * 10, 0];
+            let end = [(i + 1) * 10, 0];
+            planner.push(&drivers, &start, &end, None, None).unwrap();
+        }
+        planner.flush(250.0);
+
+        // it takes v^2 / 2a = 31.25 steps to stop
+        assert_eq!(planner.queue.len(), 4);
+        assert_eq!(planner.queue[3].exit_speed(), 0.0);
+    }
+
+    #[test]
+    fn the_queue_is_bounded() {
+        let drivers = drivers();
+        let mut planner = Planner::<CAPACITY>::new();
+
+        for i in 0..CAPACITY as i64 {
+            planner
+                .push(&drivers, &[i, 0], &[i + 1, 0], None, None)
+                .unwrap();
+        }
+
+        assert_eq!(planner.free_slots(), 0);
+        assert_eq!(
+            planner.push(&drivers, &[0, 0], &[1, 0], None, None),
+            Err(QueueFull)
+        );
+    }
+}
@@ -0,0 +1,404 @@
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use libm::F32Ext;
+
+use crate::utils::DurationHelpers;
+use core::time::Duration;
+
+/// A prediction of how a trapezoidal (or triangular) move will play out.
+///
+/// Every move is broken into three phases,
+///
+/// 1. Ramping from the initial speed to the peak speed
+/// 2. Cruising at the peak speed
+/// 3. Decelerating from the peak speed to the final speed (normally a
+///    standstill)
+///
+/// Normally the first phase speeds up, but if the motor was already moving
+/// faster than the maximum speed or in the wrong direction, it may actually be
+/// slowing down (see [`MotionProfile::new()`]).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MotionProfile {
+    acceleration_time: Duration,
+    cruise_time: Duration,
+    deceleration_time: Duration,
+    initial_speed: f32,
+    peak_speed: f32,
+    final_speed: f32,
+    acceleration: f32,
+}
+
+impl MotionProfile {
+    /// Predict the profile for moving `distance` steps, starting at
+    /// `initial_speed` and ending at a standstill.
+    ///
+    /// Speeds are signed, so an `initial_speed` with the opposite sign to
+    /// `distance` means the motor needs to stop and turn around first. If the
+    /// motor is moving too fast to stop before reaching the target it will
+    /// overshoot and come back, in which case the peak speed will have the
+    /// opposite sign to `distance`.
+    pub fn new(
+        distance: f32,
+        initial_speed: f32,
+        max_speed: f32,
+        acceleration: f32,
+    ) -> MotionProfile {
+        debug_assert!(max_speed > 0.0);
+        debug_assert!(acceleration > 0.0);
+
+        // do all calculations as if we're moving forwards
+        let reversed = distance < 0.0;
+        let (distance, initial_speed) = if reversed {
+            (-distance, -initial_speed)
+        } else {
+            (distance, initial_speed)
+        };
+
+        let stopping_distance =
+            initial_speed * initial_speed / (2.0 * acceleration);
+        let overshoot = initial_speed > 0.0 && stopping_distance > distance;
+
+        // Changing speed from v0 to v at a constant acceleration covers
+        // (v^2 - v0^2) / 2a, so the peak speed is where the two ramps cover
+        // exactly the distance to go
+        let peak_speed = if overshoot {
+            let excess =
+                initial_speed * initial_speed - 2.0 * acceleration * distance;
+            -(excess / 2.0).sqrt().min(max_speed)
+        } else {
+            let triangle = (2.0 * acceleration * distance
+                + initial_speed * initial_speed)
+                / 2.0;
+            triangle.sqrt().min(max_speed)
+        };
+
+        let first_ramp = ramp_distance(initial_speed, peak_speed, acceleration);
+        let last_ramp = ramp_distance(peak_speed, 0.0, acceleration);
+        let cruise_time = if peak_speed == 0.0 {
+            0.0
+        } else {
+            ((distance - first_ramp - last_ramp) / peak_speed).max(0.0)
+        };
+
+        MotionProfile {
+            acceleration_time: Duration::from_secs_f32_2(
+                (peak_speed - initial_speed).abs() / acceleration,
+            ),
+            cruise_time: Duration::from_secs_f32_2(cruise_time),
+            deceleration_time: Duration::from_secs_f32_2(
+                peak_speed.abs() / acceleration,
+            ),
+            initial_speed: if reversed {
+                -initial_speed
+            } else {
+                initial_speed
+            },
+            peak_speed: if reversed { -peak_speed } else { peak_speed },
+            final_speed: 0.0,
+            acceleration,
+        }
+    }
+
+    /// Predict the profile for moving forwards `distance` steps, starting at
+    /// `initial_speed` and finishing at `final_speed` instead of stopping.
+    ///
+    /// This is used when chaining moves together, so unlike
+    /// [`MotionProfile::new()`] all speeds are positive and the caller must
+    /// make sure it's possible to reach the final speed within `distance`.
+    pub fn between(
+        distance: f32,
+        initial_speed: f32,
+        final_speed: f32,
+        max_speed: f32,
+        acceleration: f32,
+    ) -> MotionProfile {
+        debug_assert!(distance >= 0.0);
+        debug_assert!(initial_speed >= 0.0 && final_speed >= 0.0);
+        debug_assert!(acceleration > 0.0);
+
+        let triangle = (2.0 * acceleration * distance
+            + initial_speed * initial_speed
+            + final_speed * final_speed)
+            / 2.0;
+        // don't let rounding errors make us go slower than the start or end
+        let peak_speed = triangle
+            .sqrt()
+            .min(max_speed)
+            .max(initial_speed)
+            .max(final_speed);
+
+        let first_ramp = ramp_distance(initial_speed, peak_speed, acceleration);
+        let last_ramp = ramp_distance(peak_speed, final_speed, acceleration);
+        let cruise_time = if peak_speed == 0.0 {
+            0.0
+        } else {
+            ((distance - first_ramp - last_ramp) / peak_speed).max(0.0)
+        };
+
+        MotionProfile {
+            acceleration_time: Duration::from_secs_f32_2(
+                (peak_speed - initial_speed) / acceleration,
+            ),
+            cruise_time: Duration::from_secs_f32_2(cruise_time),
+            deceleration_time: Duration::from_secs_f32_2(
+                (peak_speed - final_speed) / acceleration,
+            ),
+            initial_speed,
+            peak_speed,
+            final_speed,
+            acceleration,
+        }
+    }
+
+    /// A profile which covers `distance` at a constant `speed`, without
+    /// accelerating or decelerating.
+    pub(crate) fn constant_speed(distance: f32, speed: f32) -> MotionProfile {
+        debug_assert!(distance >= 0.0 && speed > 0.0);
+
+        MotionProfile {
+            acceleration_time: Duration::new(0, 0),
+            cruise_time: Duration::from_secs_f32_2(distance / speed),
+            deceleration_time: Duration::new(0, 0),
+            initial_speed: speed,
+            peak_speed: speed,
+            final_speed: speed,
+            acceleration: 0.0,
+        }
+    }
+
+    /// How long we'll spend ramping from the initial speed to the peak speed.
+    #[inline]
+    pub fn acceleration_time(&self) -> Duration { self.acceleration_time }
+
+    /// How long we'll spend cruising at the peak speed.
+    #[inline]
+    pub fn cruise_time(&self) -> Duration { self.cruise_time }
+
+    /// How long we'll spend slowing down from the peak speed to a standstill.
+    #[inline]
+    pub fn deceleration_time(&self) -> Duration { self.deceleration_time }
+
+    /// The fastest we'll go during the move, in `steps/second`.
+    #[inline]
+    pub fn peak_speed(&self) -> f32 { self.peak_speed }
+
+    /// How fast we'll be going at the end of the move, in `steps/second`.
+    #[inline]
+    pub fn final_speed(&self) -> f32 { self.final_speed }
+
+    /// The total time needed to complete the move.
+    #[inline]
+    pub fn total_time(&self) -> Duration {
+        self.acceleration_time + self.cruise_time + self.deceleration_time
+    }
+
+    /// How far we'll have travelled (in steps) a certain amount of time after
+    /// starting the move.
+    pub fn distance_at(&self, time: Duration) -> f32 {
+        let first_ramp = self.acceleration_time.as_secs_f32_2();
+        let cruise = self.cruise_time.as_secs_f32_2();
+        let last_ramp = self.deceleration_time.as_secs_f32_2();
+        let t = time.as_secs_f32_2().min(first_ramp + cruise + last_ramp);
+
+        // the first ramp may be speeding up or slowing down
+        let acceleration = if self.peak_speed >= self.initial_speed {
+            self.acceleration
+        } else {
+            -self.acceleration
+        };
+        let ramp = t.min(first_ramp);
+        let mut distance =
+            self.initial_speed * ramp + acceleration * ramp * ramp / 2.0;
+
+        if t > first_ramp {
+            distance += self.peak_speed * (t - first_ramp).min(cruise);
+        }
+
+        if t > first_ramp + cruise {
+            let deceleration = if self.peak_speed > 0.0 {
+                -self.acceleration
+            } else {
+                self.acceleration
+            };
+            let ramp = t - first_ramp - cruise;
+            distance +=
+                self.peak_speed * ramp + deceleration * ramp * ramp / 2.0;
+        }
+
+        distance
+    }
+
+    /// How fast we'll be going (in `steps/second`) a certain amount of time
+    /// after starting the move.
+    pub fn speed_at(&self, time: Duration) -> f32 {
+        let first_ramp = self.acceleration_time.as_secs_f32_2();
+        let cruise = self.cruise_time.as_secs_f32_2();
+        let last_ramp = self.deceleration_time.as_secs_f32_2();
+        let t = time.as_secs_f32_2();
+
+        if t >= first_ramp + cruise + last_ramp {
+            self.final_speed
+        } else if t >= first_ramp + cruise {
+            let ramp = t - first_ramp - cruise;
+
+            if self.peak_speed > self.final_speed {
+                self.peak_speed - self.acceleration * ramp
+            } else {
+                self.peak_speed + self.acceleration * ramp
+            }
+        } else if t >= first_ramp {
+            self.peak_speed
+        } else if self.peak_speed >= self.initial_speed {
+            self.initial_speed + self.acceleration * t
+        } else {
+            self.initial_speed - self.acceleration * t
+        }
+    }
+}
+
+/// The (signed) distance covered while changing speed at a constant
+/// acceleration.
+fn ramp_distance(from: f32, to: f32, acceleration: f32) -> f32 {
+    let distance = (to * to - from * from) / (2.0 * acceleration);
+
+    if to >= from {
+        distance
+    } else {
+        -distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Integrate the speed over the profile to find the distance travelled.
+    fn distance_travelled(profile: &MotionProfile, initial_speed: f32) -> f32 {
+        let peak = profile.peak_speed();
+        let average =
+            |a: f32, b: f32, t: Duration| (a + b) / 2.0 * t.as_secs_f32();
+
+        let distance =
+            average(initial_speed, peak, profile.acceleration_time())
+                + peak * profile.cruise_time().as_secs_f32()
+                + average(peak, 0.0, profile.deceleration_time());
+
+        // the closed-form position should agree with the integrated speed
+        let end = profile.distance_at(profile.total_time());
+        assert!((distance - end).abs() < 1e-2, "{} != {}", distance, end);
+
+        distance
+    }
+
+    fn assert_close(left: f32, right: f32) {
+        assert!((left - right).abs() < 1e-3, "{} != {}", left, right);
+    }
+
+    #[test]
+    fn trapezoidal_profile_from_rest() {
+        let profile = MotionProfile::new(1000.0, 0.0, 100.0, 50.0);
+
+        // 2 seconds to reach full speed, covering 100 steps each way
+        assert_close(profile.peak_speed(), 100.0);
+        assert_close(profile.acceleration_time().as_secs_f32(), 2.0);
+        assert_close(profile.deceleration_time().as_secs_f32(), 2.0);
+        assert_close(profile.cruise_time().as_secs_f32(), 8.0);
+        assert_close(profile.total_time().as_secs_f32(), 12.0);
+        assert_close(distance_travelled(&profile, 0.0), 1000.0);
+    }
+
+    #[test]
+    fn triangular_profile_going_backwards() {
+        let profile = MotionProfile::new(-100.0, 0.0, 100.0, 50.0);
+
+        // never reaches max speed, v = sqrt(a * d)
+        assert_close(profile.peak_speed(), -(50.0_f32 * 100.0).sqrt());
+        assert_eq!(profile.cruise_time(), Duration::new(0, 0));
+        assert_eq!(profile.acceleration_time(), profile.deceleration_time());
+        assert_close(distance_travelled(&profile, 0.0), -100.0);
+    }
+
+    #[test]
+    fn already_moving_towards_the_target() {
+        let profile = MotionProfile::new(500.0, 60.0, 100.0, 50.0);
+
+        assert_close(profile.peak_speed(), 100.0);
+        assert_close(profile.acceleration_time().as_secs_f32(), 0.8);
+        assert_close(distance_travelled(&profile, 60.0), 500.0);
+    }
+
+    #[test]
+    fn moving_in_the_wrong_direction() {
+        let profile = MotionProfile::new(500.0, -60.0, 100.0, 50.0);
+
+        // we need to stop and turn around first
+        assert_close(profile.peak_speed(), 100.0);
+        assert_close(profile.acceleration_time().as_secs_f32(), 3.2);
+        assert_close(distance_travelled(&profile, -60.0), 500.0);
+    }
+
+    #[test]
+    fn faster_than_the_max_speed() {
+        let profile = MotionProfile::new(1000.0, 150.0, 100.0, 50.0);
+
+        assert_close(profile.peak_speed(), 100.0);
+        assert_close(profile.acceleration_time().as_secs_f32(), 1.0);
+        assert_close(distance_travelled(&profile, 150.0), 1000.0);
+    }
+
+    #[test]
+    fn too_fast_to_stop_in_time() {
+        let profile = MotionProfile::new(10.0, 100.0, 100.0, 50.0);
+
+        // it takes 100 steps to stop, so we'll overshoot and come back
+        assert!(profile.peak_speed() < 0.0);
+        assert_close(distance_travelled(&profile, 100.0), 10.0);
+    }
+
+    #[test]
+    fn distance_is_continuous_and_monotonic() {
+        let profile = MotionProfile::new(1000.0, 0.0, 100.0, 50.0);
+        let mut previous = 0.0;
+
+        for millis in 0..=12_000 {
+            let distance = profile.distance_at(Duration::from_millis(millis));
+            assert!(distance >= previous);
+            assert!(distance - previous < 0.11);
+            previous = distance;
+        }
+
+        assert_close(previous, 1000.0);
+        // 100 steps accelerating, then 2 seconds cruising at 100 steps/sec
+        assert_close(profile.distance_at(Duration::from_secs(4)), 300.0);
+        assert_close(profile.distance_at(Duration::from_secs(60)), 1000.0);
+    }
+
+    #[test]
+    fn chained_profile_finishes_at_the_final_speed() {
+        let profile = MotionProfile::between(500.0, 20.0, 60.0, 100.0, 50.0);
+
+        assert_close(profile.peak_speed(), 100.0);
+        assert_close(profile.final_speed(), 60.0);
+        assert_close(profile.acceleration_time().as_secs_f32(), 1.6);
+        assert_close(profile.deceleration_time().as_secs_f32(), 0.8);
+        assert_close(profile.distance_at(profile.total_time()), 500.0);
+
+        // the speed at the very end should be the final speed
+        let just_before = profile.total_time() - Duration::from_millis(1);
+        let speed = (profile.distance_at(profile.total_time())
+            - profile.distance_at(just_before))
+            / 1e-3;
+        assert!((speed - 60.0).abs() < 0.1, "{}", speed);
+        assert!((profile.speed_at(just_before) - 60.05).abs() < 0.01);
+        assert_close(profile.speed_at(Duration::from_secs(1)), 70.0);
+        assert_close(profile.speed_at(Duration::from_secs(60)), 60.0);
+    }
+
+    #[test]
+    fn already_there() {
+        let profile = MotionProfile::new(0.0, 0.0, 100.0, 50.0);
+
+        assert_eq!(profile.total_time(), Duration::new(0, 0));
+        assert_eq!(profile.peak_speed(), 0.0);
+    }
+}
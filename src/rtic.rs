@@ -0,0 +1,332 @@
+//! Driving a [`Driver`] from a scheduler like RTIC's `Monotonic` timers.
+//!
+//! Instead of busy-polling [`Driver::poll()`], a [`MonotonicDriver`] takes a
+//! step whenever its timer fires and reports when the next step is due, so a
+//! task can be re-spawned at exactly that instant. Time is measured with
+//! [`fugit::Instant`]s, which is what RTIC's monotonics hand out. Both 64-bit
+//! and 32-bit timers are supported, although a 32-bit timer needs to be read
+//! at least once per wrap-around period (see [`MonotonicDriver`]).
+//!
+//! A typical RTIC task looks something like this:
+//!
+//! ```rust,ignore
+//! #[task(local = [stepper, step_pin])]
+//! fn step(cx: step::Context, scheduled: Instant) {
+//!     let next = cx.local.stepper.on_timer(&mut *cx.local.step_pin, scheduled).unwrap();
+//!
+//!     if let Some(next) = next {
+//!         step::spawn_at(next, next).unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! Requires the `rtic` feature.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use accel_stepper::{rtic::MonotonicDriver, Driver};
+//!
+//! type Instant = fugit::Instant<u64, 1, 1_000_000>;
+//!
+//! let mut driver = Driver::new();
+//! driver.set_max_speed(1000.0);
+//! driver.set_acceleration(500.0);
+//!
+//! let start = Instant::from_ticks(0);
+//! let mut stepper = MonotonicDriver::new(driver, start);
+//! stepper.driver_mut().move_to(100);
+//!
+//! let mut device = accel_stepper::func_device(|| {}, || {});
+//! let mut last_step = start;
+//!
+//! // pretend to be the scheduler, running the task whenever it is due
+//! let mut next = stepper.schedule(start);
+//!
+//! while let Some(now) = next {
+//!     last_step = now;
+//!     next = stepper.on_timer(&mut device, now)?;
+//! }
+//!
+//! assert_eq!(stepper.driver().current_position(), 100);
+//! assert!((last_step - start).to_millis() > 500);
+//! # Result::<(), Box<dyn std::error::Error>>::Ok(())
+//! ```
+
+use crate::{Device, Driver, Ticks};
+use core::time::Duration;
+
+/// An instant from a monotonic timer which can be moved forward by a number
+/// of ticks.
+pub trait TimerInstant: Ticks {
+    /// Add `ticks` to the instant, wrapping around on overflow.
+    fn wrapping_add_ticks(self, ticks: u64) -> Self;
+}
+
+impl<const NOM: u32, const DENOM: u32> TimerInstant
+    for fugit::Instant<u32, NOM, DENOM>
+{
+    fn wrapping_add_ticks(self, ticks: u64) -> Self {
+        Self::from_ticks(self.ticks().wrapping_add(ticks as u32))
+    }
+}
+
+impl<const NOM: u32, const DENOM: u32> TimerInstant
+    for fugit::Instant<u64, NOM, DENOM>
+{
+    fn wrapping_add_ticks(self, ticks: u64) -> Self {
+        Self::from_ticks(self.ticks().wrapping_add(ticks))
+    }
+}
+
+/// A [`Driver`] which is stepped by a task scheduled on a monotonic timer.
+///
+/// The timer counts `NOM / DENOM` second ticks using a `T` (`u32` or `u64`),
+/// the same convention as [`fugit::Instant`].
+///
+/// # Wrapping Timers
+///
+/// Readings are extended to a 64-bit tick count internally, the same way as
+/// with a [`crate::WrappingClock`]. For a 32-bit timer this only works if
+/// [`MonotonicDriver::schedule()`] or [`MonotonicDriver::on_timer()`] is
+/// called at least once per wrap-around period (about 71 minutes at 1 MHz),
+/// so if the motor may sit idle for longer than that, make sure something
+/// calls [`MonotonicDriver::schedule()`] periodically.
+pub struct MonotonicDriver<const NOM: u32, const DENOM: u32, T = u64> {
+    driver: Driver,
+    /// The instant corresponding to a [`Driver`] time of zero.
+    epoch: fugit::Instant<T, NOM, DENOM>,
+    /// The most recent timer reading, and how many ticks after the epoch it
+    /// happened.
+    latest: (fugit::Instant<T, NOM, DENOM>, u64),
+    /// When the next step is due (in ticks since the epoch), and the exact
+    /// [`Driver`] time to record for it.
+    scheduled: Option<(u64, Duration)>,
+}
+
+impl<T, const NOM: u32, const DENOM: u32> MonotonicDriver<NOM, DENOM, T>
+where
+    fugit::Instant<T, NOM, DENOM>: TimerInstant,
+{
+    /// Create a new [`MonotonicDriver`], where `now` is the timer's current
+    /// reading.
+    pub fn new(
+        driver: Driver,
+        now: fugit::Instant<T, NOM, DENOM>,
+    ) -> MonotonicDriver<NOM, DENOM, T> {
+        MonotonicDriver {
+            driver,
+            epoch: now,
+            latest: (now, 0),
+            scheduled: None,
+        }
+    }
+
+    pub fn driver(&self) -> &Driver { &self.driver }
+
+    /// Get mutable access to the underlying [`Driver`] (e.g. to start a new
+    /// move).
+    ///
+    /// The next step may be due at a different time afterwards, so make sure
+    /// to call [`MonotonicDriver::schedule()`] and re-spawn the stepping task.
+    pub fn driver_mut(&mut self) -> &mut Driver { &mut self.driver }
+
+    /// When the next step is due, as of the last call to
+    /// [`MonotonicDriver::schedule()`] or [`MonotonicDriver::on_timer()`].
+    pub fn scheduled(&self) -> Option<fugit::Instant<T, NOM, DENOM>> {
+        self.scheduled.map(|(ticks, _)| self.to_instant(ticks))
+    }
+
+    /// Work out when the next step is due, or `None` if the motor isn't
+    /// moving.
+    ///
+    /// Steps which are already overdue (e.g. when starting a move after
+    /// sitting idle) are scheduled for `now`.
+    pub fn schedule(
+        &mut self,
+        now: fugit::Instant<T, NOM, DENOM>,
+    ) -> Option<fugit::Instant<T, NOM, DENOM>> {
+        let now = self.ticks_since_epoch(now);
+        self.scheduled = self.next_step_no_earlier_than(now);
+        self.scheduled()
+    }
+
+    /// Called when the timer fires, taking a step if one is due and
+    /// returning when the next one should happen.
+    ///
+    /// Steps are recorded as happening at their scheduled time rather than
+    /// `now`, so neither latency in running the task nor rounding to the
+    /// timer's resolution will accumulate as timing errors. If the timer
+    /// fires early nothing happens and the existing schedule is returned.
+    pub fn on_timer<D: Device>(
+        &mut self,
+        device: D,
+        now: fugit::Instant<T, NOM, DENOM>,
+    ) -> Result<Option<fugit::Instant<T, NOM, DENOM>>, D::Error> {
+        let now = self.ticks_since_epoch(now);
+
+        let (due, time) = match self.scheduled {
+            Some((due, time)) if due <= now => (due, time),
+            Some(_) => return Ok(self.scheduled()),
+            None => {
+                self.scheduled = self.next_step_no_earlier_than(now);
+                return Ok(self.scheduled());
+            },
+        };
+
+        self.driver.fire_step(device, time)?;
+        self.scheduled = self.next_step_no_earlier_than(due);
+
+        Ok(self.scheduled())
+    }
+
+    /// Extend a timer reading to the number of ticks since the epoch.
+    fn ticks_since_epoch(&mut self, now: fugit::Instant<T, NOM, DENOM>) -> u64 {
+        let (previous, previous_ticks) = self.latest;
+        let ticks = previous_ticks + now.ticks_since(previous);
+
+        self.latest = (now, ticks);
+        ticks
+    }
+
+    fn next_step_no_earlier_than(
+        &self,
+        earliest: u64,
+    ) -> Option<(u64, Duration)> {
+        self.driver.next_step_time().map(|time| {
+            let ticks = to_ticks::<NOM, DENOM>(time);
+
+            if ticks < earliest {
+                (earliest, to_duration::<NOM, DENOM>(earliest))
+            } else {
+                (ticks, time)
+            }
+        })
+    }
+
+    fn to_instant(&self, ticks: u64) -> fugit::Instant<T, NOM, DENOM> {
+        self.epoch.wrapping_add_ticks(ticks)
+    }
+}
+
+/// Convert ticks since the epoch to a [`Driver`] time, rounding down.
+fn to_duration<const NOM: u32, const DENOM: u32>(ticks: u64) -> Duration {
+    let nanos =
+        u128::from(ticks) * u128::from(NOM) * 1_000_000_000 / u128::from(DENOM);
+
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// Convert a [`Driver`] time to ticks since the epoch, rounding up so steps
+/// are never taken early.
+fn to_ticks<const NOM: u32, const DENOM: u32>(time: Duration) -> u64 {
+    let nanos_per_tick = u128::from(NOM) * 1_000_000_000;
+
+    (time.as_nanos() * u128::from(DENOM)).div_ceil(nanos_per_tick) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Recorder;
+    use std::vec::Vec;
+
+    type Instant = fugit::Instant<u64, 1, 1_000_000>;
+
+    fn driver() -> Driver {
+        let mut driver = Driver::new();
+        driver.set_max_speed(2000.0);
+        driver.set_acceleration(3000.0);
+        driver.move_to(1500);
+        driver
+    }
+
+    /// Step a [`Driver`] directly, using the exact times it asks for.
+    fn reference_steps() -> Vec<(i64, Duration)> {
+        let mut driver = driver();
+        let mut device = Recorder::default();
+
+        while let Some(deadline) = driver.next_step_time() {
+            driver.fire_step(&mut device, deadline).unwrap();
+        }
+
+        device.steps
+    }
+
+    /// A pretend monotonic timer which runs the stepping task a little late
+    /// every time.
+    fn simulate(latency_ticks: u64) -> (Vec<(i64, Duration)>, Vec<Instant>) {
+        let start = Instant::from_ticks(5_000);
+        let mut stepper = MonotonicDriver::new(driver(), start);
+        let mut device = Recorder::default();
+        let mut spawned_at = Vec::new();
+        let mut next = stepper.schedule(start);
+
+        while let Some(due) = next {
+            spawned_at.push(due);
+            let now = due
+                + fugit::Duration::<u64, 1, 1_000_000>::from_ticks(
+                    latency_ticks,
+                );
+            next = stepper.on_timer(&mut device, now).unwrap();
+        }
+
+        assert_eq!(stepper.driver().current_position(), 1500);
+        (device.steps, spawned_at)
+    }
+
+    #[test]
+    fn step_times_match_the_driver_profile() {
+        let expected = reference_steps();
+
+        for &latency in &[0, 37] {
+            let (actual, spawned_at) = simulate(latency);
+
+            assert_eq!(actual, expected);
+
+            for (instant, (_, time)) in spawned_at.iter().zip(&expected) {
+                // the task should be spawned on the first tick after each
+                // step is due
+                let offset = Duration::from_micros(
+                    (*instant - Instant::from_ticks(5_000)).to_micros(),
+                );
+                assert!(offset >= *time, "{:?} vs {:?}", offset, time);
+                assert!(offset - *time < Duration::from_micros(1));
+            }
+        }
+    }
+
+    #[test]
+    fn a_32_bit_timer_can_wrap_around() {
+        type Instant = fugit::Instant<u32, 1, 1_000_000>;
+
+        // the move takes about 1.3 seconds, so the timer overflows part way
+        let start = Instant::from_ticks(u32::MAX - 500_000);
+        let mut stepper = MonotonicDriver::new(driver(), start);
+        let mut device = Recorder::default();
+        let mut next = stepper.schedule(start);
+
+        while let Some(due) = next {
+            next = stepper.on_timer(&mut device, due).unwrap();
+        }
+
+        assert_eq!(stepper.driver().current_position(), 1500);
+        assert_eq!(device.steps, reference_steps());
+    }
+
+    #[test]
+    fn timer_firing_early_does_nothing() {
+        let start = Instant::from_ticks(0);
+        let mut stepper = MonotonicDriver::new(driver(), start);
+        let mut device = Recorder::default();
+
+        let due = stepper.schedule(start).unwrap();
+        let early = stepper.on_timer(&mut device, start).unwrap();
+
+        assert_eq!(early, Some(due));
+        assert!(device.steps.is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+//! Fixtures shared by the unit tests.
+
+use crate::{Device, ManualClock, StepContext};
+use core::time::Duration;
+use std::vec::Vec;
+
+/// A clock which moves forward by a fixed amount every time it is read.
+pub(crate) fn ticking_clock() -> ManualClock {
+    ManualClock::with_auto_advance(Duration::from_micros(20))
+}
+
+/// A [`Device`] which records the position and time of each step, and
+/// whether its outputs were last enabled or disabled.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Recorder {
+    pub(crate) steps: Vec<(i64, Duration)>,
+    pub(crate) enabled: Option<bool>,
+}
+
+impl Recorder {
+    /// When each step was taken.
+    pub(crate) fn times(&self) -> Vec<Duration> {
+        self.steps.iter().map(|&(_, time)| time).collect()
+    }
+}
+
+impl Device for Recorder {
+    type Error = ();
+
+    fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+        self.steps.push((ctx.position, ctx.step_time));
+        Ok(())
+    }
+
+    fn enable_outputs(&mut self) -> Result<(), Self::Error> {
+        self.enabled = Some(true);
+        Ok(())
+    }
+
+    fn disable_outputs(&mut self) -> Result<(), Self::Error> {
+        self.enabled = Some(false);
+        Ok(())
+    }
+}
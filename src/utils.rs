@@ -90,3 +90,131 @@ impl CummulativeSteps {
         rounded_steps as i64
     }
 }
+
+/// Measures how much time has passed since something started.
+///
+/// If the clock goes backwards (e.g. because a hardware timer wrapped
+/// around), timing carries on from the new reading without losing the time
+/// measured so far, instead of stalling until the clock catches up again.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub(crate) struct Stopwatch {
+    started_at: Option<Duration>,
+    /// Time measured before the clock last went backwards.
+    carried: Duration,
+    /// The most recent reading.
+    latest: Duration,
+}
+
+impl Stopwatch {
+    /// A [`Stopwatch`] which starts on the first call to
+    /// [`Stopwatch::elapsed()`].
+    pub(crate) const fn new() -> Stopwatch {
+        Stopwatch {
+            started_at: None,
+            carried: Duration::from_secs(0),
+            latest: Duration::from_secs(0),
+        }
+    }
+
+    /// A [`Stopwatch`] which was started at `now`, `already_elapsed` ago.
+    pub(crate) const fn started(
+        now: Duration,
+        already_elapsed: Duration,
+    ) -> Stopwatch {
+        Stopwatch {
+            started_at: Some(now),
+            carried: already_elapsed,
+            latest: now,
+        }
+    }
+
+    /// How much time has passed since the [`Stopwatch`] was started.
+    pub(crate) fn elapsed(&mut self, now: Duration) -> Duration {
+        let started_at = match self.started_at {
+            Some(started_at) if now >= self.latest => started_at,
+            Some(started_at) => {
+                // the clock went backwards, so keep going from here
+                self.carried += self.latest - started_at;
+                self.started_at = Some(now);
+                now
+            },
+            None => {
+                self.started_at = Some(now);
+                now
+            },
+        };
+
+        self.latest = now;
+        self.carried + (now - started_at)
+    }
+}
+
+/// The integer square root of `n`, rounded down.
+pub(crate) fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+
+    // Newton's method, starting from a power of two which is guaranteed to
+    // be larger than the root
+    let shift = (64 - n.leading_zeros()).div_ceil(2);
+    let mut x = 1_u64 << shift;
+
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopwatch_survives_the_clock_going_backwards() {
+        let mut stopwatch = Stopwatch::new();
+
+        assert_eq!(
+            stopwatch.elapsed(Duration::from_millis(900)),
+            Duration::from_millis(0)
+        );
+        assert_eq!(
+            stopwatch.elapsed(Duration::from_millis(990)),
+            Duration::from_millis(90)
+        );
+        // the clock wraps around
+        assert_eq!(
+            stopwatch.elapsed(Duration::from_millis(10)),
+            Duration::from_millis(90)
+        );
+        assert_eq!(
+            stopwatch.elapsed(Duration::from_millis(30)),
+            Duration::from_millis(110)
+        );
+
+        let mut stopwatch = Stopwatch::started(
+            Duration::from_millis(5),
+            Duration::from_millis(20),
+        );
+        assert_eq!(
+            stopwatch.elapsed(Duration::from_millis(15)),
+            Duration::from_millis(30)
+        );
+    }
+
+    #[test]
+    fn integer_square_root() {
+        let inputs = [0, 1, 2, 3, 4, 15, 16, 17, 1 << 40, u64::MAX];
+
+        for &n in &inputs {
+            let root = isqrt(n);
+            assert!(u128::from(root) * u128::from(root) <= u128::from(n));
+            assert!(
+                u128::from(root + 1) * u128::from(root + 1) > u128::from(n)
+            );
+        }
+    }
+}